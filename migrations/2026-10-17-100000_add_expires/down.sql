@@ -0,0 +1,6 @@
+ALTER TABLE files
+DROP COLUMN expires;
+ALTER TABLE links
+DROP COLUMN expires;
+ALTER TABLE texts
+DROP COLUMN expires;
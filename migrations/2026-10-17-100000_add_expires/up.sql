@@ -0,0 +1,6 @@
+ALTER TABLE files
+ADD expires INTEGER;
+ALTER TABLE links
+ADD expires INTEGER;
+ALTER TABLE texts
+ADD expires INTEGER;
@@ -0,0 +1,6 @@
+ALTER TABLE files
+DROP COLUMN views_left;
+ALTER TABLE links
+DROP COLUMN views_left;
+ALTER TABLE texts
+DROP COLUMN views_left;
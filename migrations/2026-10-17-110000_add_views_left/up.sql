@@ -0,0 +1,6 @@
+ALTER TABLE files
+ADD views_left INTEGER;
+ALTER TABLE links
+ADD views_left INTEGER;
+ALTER TABLE texts
+ADD views_left INTEGER;
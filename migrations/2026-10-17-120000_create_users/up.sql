@@ -0,0 +1,7 @@
+CREATE TABLE users (
+  id INTEGER NOT NULL PRIMARY KEY,
+  username TEXT NOT NULL UNIQUE,
+  password_hash TEXT NOT NULL,
+  admin BOOLEAN NOT NULL DEFAULT false,
+  created INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
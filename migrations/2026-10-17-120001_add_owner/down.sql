@@ -0,0 +1,6 @@
+ALTER TABLE files
+DROP COLUMN owner;
+ALTER TABLE links
+DROP COLUMN owner;
+ALTER TABLE texts
+DROP COLUMN owner;
@@ -0,0 +1,6 @@
+ALTER TABLE files
+ADD owner TEXT;
+ALTER TABLE links
+ADD owner TEXT;
+ALTER TABLE texts
+ADD owner TEXT;
@@ -0,0 +1,9 @@
+CREATE TABLE tokens (
+  id INTEGER NOT NULL PRIMARY KEY,
+  owner TEXT NOT NULL,
+  name TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  scopes TEXT NOT NULL,
+  expires INTEGER,
+  created INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
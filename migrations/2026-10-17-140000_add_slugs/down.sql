@@ -0,0 +1,9 @@
+DROP INDEX files_slug;
+ALTER TABLE files
+DROP COLUMN slug;
+DROP INDEX links_slug;
+ALTER TABLE links
+DROP COLUMN slug;
+DROP INDEX texts_slug;
+ALTER TABLE texts
+DROP COLUMN slug;
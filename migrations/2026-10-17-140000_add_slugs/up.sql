@@ -0,0 +1,9 @@
+ALTER TABLE files
+ADD slug TEXT;
+CREATE UNIQUE INDEX files_slug ON files (slug);
+ALTER TABLE links
+ADD slug TEXT;
+CREATE UNIQUE INDEX links_slug ON links (slug);
+ALTER TABLE texts
+ADD slug TEXT;
+CREATE UNIQUE INDEX texts_slug ON texts (slug);
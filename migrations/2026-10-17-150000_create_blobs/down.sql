@@ -0,0 +1,3 @@
+ALTER TABLE files
+DROP COLUMN hash;
+DROP TABLE blobs;
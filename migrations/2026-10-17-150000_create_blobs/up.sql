@@ -0,0 +1,6 @@
+CREATE TABLE blobs (
+  hash TEXT NOT NULL PRIMARY KEY,
+  refcount INTEGER NOT NULL
+);
+ALTER TABLE files
+ADD hash TEXT;
@@ -0,0 +1,10 @@
+CREATE TABLE uploads (
+  id TEXT NOT NULL PRIMARY KEY,
+  owner TEXT NOT NULL,
+  length BIGINT NOT NULL,
+  filename TEXT NOT NULL,
+  expires INTEGER,
+  views_left INTEGER,
+  slug TEXT,
+  created INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
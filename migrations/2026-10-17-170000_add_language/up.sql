@@ -0,0 +1,2 @@
+ALTER TABLE texts
+ADD language TEXT;
@@ -0,0 +1,5 @@
+ALTER TABLE texts
+ADD format TEXT NOT NULL DEFAULT 'plain';
+UPDATE texts
+SET format = 'code'
+WHERE highlight;
@@ -0,0 +1,2 @@
+DROP TRIGGER texts_delete_revisions;
+DROP TABLE text_revisions;
@@ -0,0 +1,18 @@
+CREATE TABLE text_revisions (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  text_id INTEGER NOT NULL,
+  revision INTEGER NOT NULL,
+  contents TEXT NOT NULL,
+  format TEXT NOT NULL,
+  language TEXT,
+  created INTEGER NOT NULL,
+  author TEXT,
+  UNIQUE (text_id, revision)
+);
+-- REPLACE doesn't fire delete triggers, so revisions only go away with the text itself
+CREATE TRIGGER texts_delete_revisions
+AFTER DELETE ON texts
+BEGIN
+  DELETE FROM text_revisions
+  WHERE text_id = OLD.id;
+END;
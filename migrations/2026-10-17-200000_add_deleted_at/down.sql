@@ -0,0 +1,6 @@
+ALTER TABLE files
+DROP COLUMN deleted_at;
+ALTER TABLE links
+DROP COLUMN deleted_at;
+ALTER TABLE texts
+DROP COLUMN deleted_at;
@@ -0,0 +1,6 @@
+ALTER TABLE files
+ADD deleted_at INTEGER;
+ALTER TABLE links
+ADD deleted_at INTEGER;
+ALTER TABLE texts
+ADD deleted_at INTEGER;
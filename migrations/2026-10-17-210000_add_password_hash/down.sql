@@ -0,0 +1,8 @@
+ALTER TABLE files
+DROP COLUMN password_hash;
+ALTER TABLE links
+DROP COLUMN password_hash;
+ALTER TABLE texts
+DROP COLUMN password_hash;
+ALTER TABLE uploads
+DROP COLUMN password_hash;
@@ -0,0 +1,8 @@
+ALTER TABLE files
+ADD password_hash TEXT;
+ALTER TABLE links
+ADD password_hash TEXT;
+ALTER TABLE texts
+ADD password_hash TEXT;
+ALTER TABLE uploads
+ADD password_hash TEXT;
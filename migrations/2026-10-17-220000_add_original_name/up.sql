@@ -0,0 +1,2 @@
+ALTER TABLE files
+ADD original_name TEXT;
@@ -0,0 +1,4 @@
+ALTER TABLE files
+DROP COLUMN content_type;
+ALTER TABLE files
+DROP COLUMN size;
@@ -0,0 +1,4 @@
+ALTER TABLE files
+ADD content_type TEXT;
+ALTER TABLE files
+ADD size BIGINT;
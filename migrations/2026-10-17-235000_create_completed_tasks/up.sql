@@ -0,0 +1,4 @@
+CREATE TABLE completed_tasks (
+  name TEXT NOT NULL PRIMARY KEY,
+  completed INTEGER NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Texts written before authors were recorded were written by their owner
+ALTER TABLE texts
+ADD author TEXT;
+UPDATE texts
+SET author = owner;
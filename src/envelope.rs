@@ -0,0 +1,152 @@
+//! Envelopes of texts encrypted in the browser
+//!
+//! The server never sees the key, which stays in the URL fragment, so it can only check
+//! that an envelope is well-formed before storing it.
+
+use std::ops::RangeInclusive;
+
+/// Version of the envelope format
+const VERSION: u32 = 1;
+/// Cipher used to encrypt contents
+const CIPHER: &str = "aes-256-gcm";
+/// Function deriving the encryption key from the key in the URL fragment
+const KDF: &str = "pbkdf2-sha256";
+/// Accepted numbers of key derivation iterations
+const ITERATIONS: RangeInclusive<u32> = 1_000..=10_000_000;
+/// Minimum size of the key derivation salt in bytes
+const MIN_SALT_SIZE: usize = 16;
+/// Size of the AES-GCM initialization vector in bytes
+const IV_SIZE: usize = 12;
+/// Size of the AES-GCM authentication tag ending the ciphertext, in bytes
+const TAG_SIZE: usize = 16;
+
+/// Ciphertext of an encrypted text along with what's needed to decrypt it
+///
+/// Binary fields are encoded with standard base64.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Envelope {
+    /// Version of the envelope format
+    pub version: u32,
+    /// Cipher the contents were encrypted with
+    pub cipher: String,
+    /// Function the encryption key was derived with
+    pub kdf: String,
+    /// Number of key derivation iterations
+    pub iterations: u32,
+    /// Key derivation salt
+    pub salt: String,
+    /// Initialization vector
+    pub iv: String,
+    /// Encrypted contents followed by their authentication tag
+    pub ciphertext: String,
+}
+
+/// Decodes a base64 field, checking that its size is acceptable
+fn decode(name: &str, value: &str, min_size: usize) -> Result<Vec<u8>, String> {
+    let bytes = base64::decode(value).map_err(|_| format!("Invalid base64 in `{}`", name))?;
+    if bytes.len() < min_size {
+        return Err(format!("`{}` is too short", name));
+    }
+    Ok(bytes)
+}
+
+/// Parses and validates an envelope, returning it normalized
+pub fn validate(contents: &str) -> Result<String, String> {
+    let envelope: Envelope =
+        serde_json::from_str(contents).map_err(|e| format!("Invalid envelope: {}", e))?;
+    if envelope.version != VERSION {
+        return Err(format!("Unsupported envelope version {}", envelope.version));
+    }
+    if envelope.cipher != CIPHER {
+        return Err(format!("Unsupported cipher `{}`", envelope.cipher));
+    }
+    if envelope.kdf != KDF {
+        return Err(format!(
+            "Unsupported key derivation function `{}`",
+            envelope.kdf
+        ));
+    }
+    if !ITERATIONS.contains(&envelope.iterations) {
+        return Err(format!(
+            "Key derivation iterations must be between {} and {}",
+            ITERATIONS.start(),
+            ITERATIONS.end()
+        ));
+    }
+    decode("salt", &envelope.salt, MIN_SALT_SIZE)?;
+    if decode("iv", &envelope.iv, IV_SIZE)?.len() != IV_SIZE {
+        return Err(format!("`iv` must be {} bytes long", IV_SIZE));
+    }
+    decode("ciphertext", &envelope.ciphertext, TAG_SIZE)?;
+
+    serde_json::to_string(&envelope).map_err(|e| format!("Invalid envelope: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// An envelope which is valid until one of its fields is changed
+    fn valid() -> Value {
+        json!({
+            "version": VERSION,
+            "cipher": CIPHER,
+            "kdf": KDF,
+            "iterations": 100_000,
+            "salt": base64::encode(&[1; MIN_SALT_SIZE]),
+            "iv": base64::encode(&[2; IV_SIZE]),
+            "ciphertext": base64::encode(&[3; TAG_SIZE + 5]),
+        })
+    }
+
+    /// Validates the valid envelope with one field replaced
+    fn with(field: &str, value: Value) -> Result<String, String> {
+        let mut envelope = valid();
+        envelope[field] = value;
+        validate(&envelope.to_string())
+    }
+
+    #[test]
+    fn accepts_valid_envelopes() {
+        let normalized = validate(&valid().to_string()).unwrap();
+        let parsed: Value = serde_json::from_str(&normalized).unwrap();
+        assert_eq!(parsed, valid());
+    }
+
+    #[test]
+    fn rejects_unsupported_formats() {
+        assert!(with("version", json!(2)).is_err());
+        assert!(with("cipher", json!("aes-128-cbc")).is_err());
+        assert!(with("kdf", json!("scrypt")).is_err());
+        assert!(with("iterations", json!(10)).is_err());
+        assert!(with("iterations", json!(u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_base64() {
+        assert!(with("salt", json!("not base64!")).is_err());
+        assert!(with("iv", json!("AAAA====AAAA")).is_err());
+        assert!(with("ciphertext", json!("%%%%")).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_sizes() {
+        assert!(with("iv", json!(base64::encode(&[2; IV_SIZE - 1]))).is_err());
+        assert!(with("iv", json!(base64::encode(&[2; IV_SIZE + 4]))).is_err());
+        assert!(with("salt", json!(base64::encode(&[1; MIN_SALT_SIZE - 1]))).is_err());
+        assert!(with("ciphertext", json!(base64::encode(&[3; TAG_SIZE - 1]))).is_err());
+        assert!(with("salt", json!(base64::encode(&[1; MIN_SALT_SIZE * 2]))).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_envelopes() {
+        assert!(with("key", json!("leaked")).is_err());
+        assert!(with("iterations", json!("100000")).is_err());
+        assert!(validate("plain text").is_err());
+        let mut envelope = valid();
+        envelope.as_object_mut().unwrap().remove("iv");
+        assert!(validate(&envelope.to_string()).is_err());
+    }
+}
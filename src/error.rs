@@ -0,0 +1,233 @@
+//! Errors returned by route handlers
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    error::BlockingError,
+    http::{header, HeaderValue, StatusCode},
+    HttpResponse, ResponseError,
+};
+use std::{fmt, future::Future};
+
+/// Header identifying a request in responses and logs
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Error responding with its status and a message, as plain text or JSON
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request is invalid
+    BadRequest(String),
+    /// The request must authenticate with the given scheme
+    Unauthorized(&'static str, String),
+    /// The authenticated user isn't allowed to do this
+    Forbidden(String),
+    /// The entry doesn't exist
+    NotFound,
+    /// The request conflicts with an existing entry
+    Conflict(String),
+    /// The entry has expired
+    Gone,
+    /// The request body is too large
+    PayloadTooLarge(String),
+    /// The request body has an unsupported type
+    UnsupportedMediaType(String),
+    /// Something went wrong on the server, with a cause which is only logged
+    Internal(String),
+}
+
+/// Body of errors sent to clients accepting JSON
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'static str,
+    message: &'a str,
+}
+
+impl ApiError {
+    /// Creates an internal error, keeping its cause for the logs
+    pub fn internal(cause: impl fmt::Display) -> Self {
+        ApiError::Internal(cause.to_string())
+    }
+
+    /// Returns the machine-readable code of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(..) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Gone => "expired",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    /// Returns the message shown to clients, which never includes internal causes
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(m)
+            | ApiError::Unauthorized(_, m)
+            | ApiError::Forbidden(m)
+            | ApiError::Conflict(m)
+            | ApiError::PayloadTooLarge(m)
+            | ApiError::UnsupportedMediaType(m) => m,
+            ApiError::NotFound => "Not found",
+            ApiError::Gone => "Expired",
+            ApiError::Internal(_) => "Internal server error",
+        }
+    }
+
+    /// Builds the response to the error with the given body
+    fn response(&self, content_type: &str, body: String) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        response.header(header::CONTENT_TYPE, content_type);
+        if let ApiError::Unauthorized(scheme, _) = self {
+            response.header(
+                header::WWW_AUTHENTICATE,
+                format!("{} realm=\"filite\"", scheme),
+            );
+        }
+        response.body(body)
+    }
+
+    /// Builds the JSON response to the error
+    fn json_response(&self) -> HttpResponse {
+        let body = ErrorBody {
+            error: self.code(),
+            message: self.message(),
+        };
+        match serde_json::to_string(&body) {
+            Ok(b) => self.response("application/json", b),
+            Err(_) => self.error_response(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Internal(cause) => write!(f, "{}", cause),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(..) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Gone => StatusCode::GONE,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.response("text/plain; charset=utf-8", self.message().to_owned())
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(error: diesel::result::Error) -> Self {
+        match error {
+            diesel::result::Error::NotFound => ApiError::NotFound,
+            e => ApiError::internal(e),
+        }
+    }
+}
+
+impl From<BlockingError<diesel::result::Error>> for ApiError {
+    fn from(error: BlockingError<diesel::result::Error>) -> Self {
+        match error {
+            BlockingError::Error(e) => e.into(),
+            BlockingError::Canceled => ApiError::internal("Blocking operation canceled"),
+        }
+    }
+}
+
+impl From<BlockingError<ApiError>> for ApiError {
+    fn from(error: BlockingError<ApiError>) -> Self {
+        match error {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => ApiError::internal("Blocking operation canceled"),
+        }
+    }
+}
+
+/// Whether a request accepts JSON responses
+fn accepts_json(request: &ServiceRequest) -> bool {
+    request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|a| a.to_str().ok())
+        .is_some_and(|a| a.contains("application/json"))
+}
+
+/// Returns the ID a request was given by a proxy, or a new random one
+fn request_id(request: &ServiceRequest) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|i| i.to_str().ok())
+        .filter(|i| !i.is_empty() && i.len() <= 64)
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("{}", radix_fmt::radix_36(rand::random::<u64>())))
+}
+
+/// Middleware identifying requests and reporting errors
+///
+/// Every response gets an `X-Request-Id` header, internal errors are logged along with
+/// their cause and errors are sent as JSON to clients accepting it.
+pub fn middleware<S>(
+    request: ServiceRequest,
+    service: &mut S,
+) -> impl Future<Output = Result<ServiceResponse, actix_web::Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let id = request_id(&request);
+    let json = accepts_json(&request);
+    let call = service.call(request);
+
+    async move {
+        let mut response = call.await?;
+        let rendered = match response.response().error() {
+            Some(e) => match e.as_error::<ApiError>() {
+                Some(error) => {
+                    if let ApiError::Internal(cause) = error {
+                        let request = response.request();
+                        eprintln!(
+                            "[{}] {} {}: {}",
+                            id,
+                            request.method(),
+                            request.path(),
+                            cause
+                        );
+                    }
+                    if json {
+                        Some(error.json_response())
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            },
+            None => None,
+        };
+        if let Some(r) = rendered {
+            response = response.into_response(r);
+        }
+
+        if let Ok(value) = HeaderValue::from_str(&id) {
+            response
+                .headers_mut()
+                .insert(header::HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(response)
+    }
+}
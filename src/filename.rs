@@ -0,0 +1,158 @@
+//! Normalization of the names files are uploaded with
+//!
+//! Uploaded names are only trusted for display. The normalized name is what gets stored and
+//! used to guess content types, so it can't contain anything a filesystem or header would
+//! interpret.
+
+/// Maximum length of a normalized name in bytes
+const MAX_LENGTH: usize = 255;
+/// Maximum length of an extension kept when a name is truncated, in bytes
+const MAX_EXTENSION_LENGTH: usize = 16;
+/// Characters which aren't allowed in names on common filesystems
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+/// Device names reserved on Windows, whatever their extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// Name used when nothing is left after normalization
+const FALLBACK: &str = "file";
+
+/// Truncates a string to at most `max` bytes without splitting a character
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = max.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Normalizes the name a file was uploaded with
+///
+/// Directories are dropped, control and reserved characters are replaced, leading and trailing
+/// dots and spaces are trimmed, reserved device names are prefixed and the result is capped to
+/// 255 bytes, keeping the extension.
+pub fn sanitize(name: &str) -> String {
+    // Browsers and clients may send full paths using either separator
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || RESERVED_CHARS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if name.is_empty() {
+        return FALLBACK.to_owned();
+    }
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    let mut name = if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        format!("_{}", name)
+    } else {
+        name.to_owned()
+    };
+
+    if name.len() > MAX_LENGTH {
+        let extension = match name.rfind('.') {
+            Some(i) if name.len() - i <= MAX_EXTENSION_LENGTH => name[i..].to_owned(),
+            _ => String::new(),
+        };
+        let stem = truncate(&name, MAX_LENGTH - extension.len())
+            .trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+        name = format!("{}{}", stem, extension);
+    }
+    name
+}
+
+/// Returns a name made only of printable ASCII characters, for clients which don't support
+/// UTF-8 names
+pub fn ascii_fallback(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_directories() {
+        assert_eq!(sanitize("/etc/passwd"), "passwd");
+        assert_eq!(sanitize("C:\\Users\\me\\report.pdf"), "report.pdf");
+        assert_eq!(sanitize("photos/2020\\beach.jpg"), "beach.jpg");
+        assert_eq!(sanitize("notes/"), FALLBACK);
+    }
+
+    #[test]
+    fn trims_dots() {
+        assert_eq!(sanitize(".."), FALLBACK);
+        assert_eq!(sanitize("../../secret"), "secret");
+        assert_eq!(sanitize("..hidden.txt.."), "hidden.txt");
+        assert_eq!(sanitize("  name.txt  "), "name.txt");
+        assert_eq!(sanitize(""), FALLBACK);
+    }
+
+    #[test]
+    fn replaces_control_and_reserved_characters() {
+        assert_eq!(sanitize("a\nb\r\0c.txt"), "a_b__c.txt");
+        assert_eq!(sanitize("what?<is>:this|\"*.txt"), "what__is__this___.txt");
+        assert_eq!(sanitize("\u{7f}\u{1b}.txt"), "__.txt");
+    }
+
+    #[test]
+    fn prefixes_reserved_names() {
+        assert_eq!(sanitize("CON"), "_CON");
+        assert_eq!(sanitize("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize("Com1.tar.gz"), "_Com1.tar.gz");
+        assert_eq!(sanitize("lpt9 .log"), "_lpt9 .log");
+        assert_eq!(sanitize("console.txt"), "console.txt");
+        assert_eq!(sanitize("COM10"), "COM10");
+    }
+
+    #[test]
+    fn truncates_keeping_the_extension() {
+        let name = sanitize(&format!("{}.tar.gz", "a".repeat(300)));
+        assert_eq!(name.len(), MAX_LENGTH);
+        assert!(name.ends_with("a.gz"));
+
+        let name = sanitize(&format!("{}.{}", "a".repeat(300), "b".repeat(20)));
+        assert_eq!(name, "a".repeat(MAX_LENGTH));
+    }
+
+    #[test]
+    fn truncates_at_character_boundaries() {
+        // Two bytes each, so the limit falls in the middle of one
+        let name = sanitize(&format!("{}.txt", "é".repeat(200)));
+        assert_eq!(name, format!("{}.txt", "é".repeat(125)));
+        assert!(name.len() <= MAX_LENGTH);
+
+        let name = sanitize(&"😀".repeat(100));
+        assert_eq!(name, "😀".repeat(63));
+    }
+
+    #[test]
+    fn keeps_names_which_are_already_valid() {
+        assert_eq!(sanitize("résumé 2020.pdf"), "résumé 2020.pdf");
+        assert_eq!(sanitize(".bashrc"), "bashrc");
+    }
+
+    #[test]
+    fn ascii_fallback_replaces_non_ascii() {
+        assert_eq!(ascii_fallback("résumé 2020.pdf"), "r_sum_ 2020.pdf");
+        assert_eq!(ascii_fallback("😀.png"), "_.png");
+        assert_eq!(ascii_fallback("tab\there"), "tab_here");
+        assert_eq!(ascii_fallback("plain-name_1.txt"), "plain-name_1.txt");
+    }
+}
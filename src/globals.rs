@@ -1,36 +1,47 @@
-pub const KEY: &[u8; 32] = include_bytes!(concat!(env!("OUT_DIR"), "/key"));
-
 lazy_static! {
-    pub static ref EMPTY_HASH: Vec<u8> = crate::setup::hash(b"");
     pub static ref POOL: crate::Pool =
         crate::setup::create_pool(&CONFIG.database_url, CONFIG.pool_size);
+    pub static ref STORAGE: Box<dyn crate::storage::Storage> =
+        crate::storage::from_config(&CONFIG.storage, &CONFIG.files_dir).unwrap_or_else(|e| {
+            eprintln!("Can't create storage backend: {}", e);
+            std::process::exit(1);
+        });
+    pub static ref HIGHLIGHTER: crate::highlight::Highlighter =
+        crate::highlight::Highlighter::new(&CONFIG.highlight).unwrap_or_else(|e| {
+            eprintln!("Can't create syntax highlighter: {}", e);
+            std::process::exit(1);
+        });
+    /// Hash verified when logging in as an unknown user, so it takes as long as for known ones
+    pub static ref DUMMY_PASSWORD_HASH: String = crate::setup::hash_password(b"");
 }
 
 #[cfg(feature = "dev")]
 lazy_static! {
     pub static ref CONFIG: crate::setup::Config = crate::setup::Config::debug();
-    pub static ref PASSWORD_HASH: Vec<u8> = {
-        dotenv::dotenv().ok();
-        let password = crate::get_env!("PASSWD");
-        crate::setup::hash(password.as_bytes())
-    };
+    pub static ref SECRET: Vec<u8> = crate::setup::generate_secret();
+    pub static ref LEGACY_PASSWORD: bool = false;
 }
 
 #[cfg(not(feature = "dev"))]
 lazy_static! {
     pub static ref CONFIG: crate::setup::Config =
-        crate::setup::init(std::env::args().fold(0, |mode, a| if &a == "init" {
-            2
-        } else if &a == "passwd" {
-            1
-        } else {
-            mode
-        }));
-    pub static ref PASSWORD_HASH: Vec<u8> = {
-        let password_path = crate::setup::get_password_path();
-        std::fs::read(&password_path).unwrap_or_else(|e| {
-            eprintln!("Can't read password hash from disk: {}", e);
+        crate::setup::init(crate::setup::is_init_command());
+    pub static ref SECRET: Vec<u8> = {
+        let secret_path = crate::setup::get_secret_path();
+        let secret = std::fs::read(&secret_path).unwrap_or_else(|e| {
+            eprintln!("Can't read cookie secret from disk: {}", e);
+            std::process::exit(1);
+        });
+        if secret.len() < crate::setup::MIN_SECRET_LENGTH {
+            eprintln!(
+                "Cookie secret at {} is shorter than {} bytes. Delete it to generate a new one.",
+                secret_path.display(),
+                crate::setup::MIN_SECRET_LENGTH
+            );
             std::process::exit(1);
-        })
+        }
+        secret
     };
+    /// Whether a single password was set by a version of filite without users
+    pub static ref LEGACY_PASSWORD: bool = crate::setup::get_password_path().exists();
 }
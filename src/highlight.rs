@@ -0,0 +1,107 @@
+//! Server-side syntax highlighting using syntect
+
+use crate::setup::HighlightConfig;
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::{self, ClassStyle, ClassedHTMLGenerator},
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+    Error,
+};
+
+/// Theme used when the configured one doesn't exist
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+/// Class style of code highlighted with classes, whose container is the `hl-code` class
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Highlighter rendering texts to HTML with the configured theme
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+    css: String,
+}
+
+impl Highlighter {
+    /// Creates a highlighter using the languages and theme described by the config
+    pub fn new(config: &HighlightConfig) -> Result<Self, String> {
+        let syntaxes = match &config.syntaxes_dir {
+            Some(dir) => {
+                let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+                builder
+                    .add_from_folder(dir, true)
+                    .map_err(|e| format!("Can't load syntaxes from {}: {}", dir.display(), e))?;
+                builder.build()
+            }
+            None => SyntaxSet::load_defaults_newlines(),
+        };
+
+        let mut themes = ThemeSet::load_defaults();
+        if let Some(dir) = &config.themes_dir {
+            themes
+                .add_from_folder(dir)
+                .map_err(|e| format!("Can't load themes from {}: {}", dir.display(), e))?;
+        }
+        // Configs written for highlight.js name themes which don't exist anymore
+        let theme = match themes.themes.remove(&config.theme) {
+            Some(t) => t,
+            None => {
+                let mut names: Vec<_> = themes.themes.keys().map(String::as_str).collect();
+                names.sort_unstable();
+                eprintln!(
+                    "Unknown highlight theme {}, using {} instead. Available themes are: {}",
+                    config.theme,
+                    DEFAULT_THEME,
+                    names.join(", ")
+                );
+                themes.themes.remove(DEFAULT_THEME).unwrap_or_default()
+            }
+        };
+
+        let css = html::css_for_theme_with_class_style(&theme, CLASS_STYLE)
+            .map_err(|e| format!("Can't generate theme stylesheet: {}", e))?;
+
+        Ok(Self {
+            syntaxes,
+            theme,
+            css,
+        })
+    }
+
+    /// Returns the syntax of a language given its name or file extension, if it's known
+    pub fn find_syntax(&self, language: &str) -> Option<&SyntaxReference> {
+        self.syntaxes.find_syntax_by_token(language)
+    }
+
+    /// Renders text to a highlighted HTML `<pre>` element with inline styles
+    pub fn highlight(&self, contents: &str, language: Option<&str>) -> Result<String, Error> {
+        let syntax = self.guess_syntax(contents, language);
+        html::highlighted_html_for_string(contents, &self.syntaxes, syntax, &self.theme)
+    }
+
+    /// Renders code to HTML `<span>` elements styled by the classes of `css`
+    ///
+    /// Unlike `highlight`, the result has no inline styles so it can go through HTML sanitization.
+    pub fn highlight_classed(&self, code: &str, language: Option<&str>) -> Result<String, Error> {
+        let syntax = self.guess_syntax(code, language);
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntaxes, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            generator.parse_html_for_line_which_includes_newline(line)?;
+        }
+        Ok(generator.finalize())
+    }
+
+    /// Returns the stylesheet of the theme for code highlighted with classes
+    pub fn css(&self) -> &str {
+        &self.css
+    }
+
+    /// Returns the syntax of a language, guessing it from the first line when it isn't known
+    fn guess_syntax(&self, contents: &str, language: Option<&str>) -> &SyntaxReference {
+        language
+            .and_then(|l| self.find_syntax(l))
+            .or_else(|| self.syntaxes.find_syntax_by_first_line(contents))
+            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text())
+    }
+}
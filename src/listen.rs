@@ -0,0 +1,225 @@
+//! Addresses the webserver listens on
+
+use std::{fmt, str::FromStr};
+
+#[cfg(unix)]
+use crate::setup::SocketConfig;
+#[cfg(unix)]
+use std::{
+    ffi::CString,
+    fs,
+    io::{self, ErrorKind},
+    os::unix::fs::{chown, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Address to listen on
+#[derive(Clone, Debug, PartialEq)]
+pub enum Listener {
+    /// TCP socket bound to a host and port
+    Tcp(String, u16),
+    /// Unix domain socket bound to a path
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Listener {
+    /// Returns the port of a TCP listener
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Listener::Tcp(_, port) => Some(*port),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+        }
+    }
+
+    /// Returns the same TCP host listening on another port
+    pub fn with_port(&self, port: u16) -> Option<Self> {
+        match self {
+            Listener::Tcp(host, _) => Some(Listener::Tcp(host.clone(), port)),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+        }
+    }
+}
+
+impl FromStr for Listener {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return match path {
+                "" => Err("Missing Unix domain socket path".to_owned()),
+                path => Ok(Listener::Unix(PathBuf::from(path))),
+            };
+            #[cfg(not(unix))]
+            return Err(format!(
+                "Unix domain sockets aren't supported on this platform: {}",
+                path
+            ));
+        }
+
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("Missing port in {}", s))?;
+        let port = port.parse().map_err(|_| format!("Invalid port in {}", s))?;
+        // IPv6 addresses contain colons, so they must be in brackets to tell them from the port
+        let host = match host.strip_prefix('[') {
+            Some(ipv6) => ipv6
+                .strip_suffix(']')
+                .filter(|h| !h.contains(['[', ']']))
+                .ok_or_else(|| format!("Invalid IPv6 address in {}", s))?,
+            None if host.contains([':', '[', ']']) => {
+                return Err(format!("IPv6 addresses must be in brackets in {}", s))
+            }
+            None => host,
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in {}", s));
+        }
+        Ok(Listener::Tcp(host.to_owned(), port))
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Listener::Tcp(host, port) if host.contains(':') => write!(f, "[{}]:{}", host, port),
+            Listener::Tcp(host, port) => write!(f, "{}:{}", host, port),
+            #[cfg(unix)]
+            Listener::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Removes a socket file left behind by a previous run
+#[cfg(unix)]
+pub fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            "file exists and isn't a socket",
+        )),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Applies the configured permissions and group to a socket file
+#[cfg(unix)]
+pub fn set_socket_permissions(path: &Path, config: &SocketConfig) -> io::Result<()> {
+    if let Some(group) = &config.group {
+        chown(path, None, Some(resolve_group(group)?))?;
+    }
+    if let Some(mode) = &config.mode {
+        let mode = u32::from_str_radix(mode, 8)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "invalid socket mode"))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Returns the id of a group given its name or numeric id
+#[cfg(unix)]
+fn resolve_group(group: &str) -> io::Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+
+    let not_found = || io::Error::new(ErrorKind::NotFound, format!("unknown group {}", group));
+    let name = CString::new(group).map_err(|_| not_found())?;
+    // SAFETY: `name` is a valid C string and the returned entry is read before any other call
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(not_found());
+    }
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tcp_addresses() {
+        assert_eq!(
+            "0.0.0.0:8080".parse(),
+            Ok(Listener::Tcp("0.0.0.0".to_owned(), 8080))
+        );
+        assert_eq!(
+            "localhost:80".parse(),
+            Ok(Listener::Tcp("localhost".to_owned(), 80))
+        );
+    }
+
+    #[test]
+    fn parses_ipv6_in_brackets() {
+        assert_eq!(
+            "[::]:8080".parse(),
+            Ok(Listener::Tcp("::".to_owned(), 8080))
+        );
+        assert_eq!(
+            "[fe80::1%eth0]:443".parse(),
+            Ok(Listener::Tcp("fe80::1%eth0".to_owned(), 443))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parses_unix_paths() {
+        assert_eq!(
+            "unix:/run/filite/filite.sock".parse(),
+            Ok(Listener::Unix(PathBuf::from("/run/filite/filite.sock")))
+        );
+        assert_eq!(
+            "unix:relative:name.sock".parse(),
+            Ok(Listener::Unix(PathBuf::from("relative:name.sock")))
+        );
+        assert!("unix:".parse::<Listener>().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        for address in &[
+            "",
+            "localhost",
+            "localhost:",
+            ":8080",
+            "[]:8080",
+            "localhost:http",
+            "localhost:65536",
+            "localhost:-1",
+            "::1:8080",
+            "[::1:8080",
+            "::1]:8080",
+            "[[::1]]:8080",
+        ] {
+            assert!(address.parse::<Listener>().is_err(), "{} parsed", address);
+        }
+    }
+
+    #[test]
+    fn displays_parseable_addresses() {
+        for address in &["127.0.0.1:8080", "[::1]:8080", "unix:/tmp/filite.sock"] {
+            assert_eq!(address.parse::<Listener>().unwrap().to_string(), *address);
+        }
+    }
+
+    #[test]
+    fn changes_tcp_ports() {
+        let listener: Listener = "[::]:80".parse().unwrap();
+        assert_eq!(listener.port(), Some(80));
+        let https = listener.with_port(443).unwrap();
+        assert_eq!(https.to_string(), "[::]:443");
+        assert_eq!(https.port(), Some(443));
+
+        #[cfg(unix)]
+        {
+            let socket: Listener = "unix:/tmp/filite.sock".parse().unwrap();
+            assert_eq!(socket.port(), None);
+            assert!(socket.with_port(443).is_none());
+        }
+    }
+}
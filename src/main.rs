@@ -9,19 +9,29 @@ extern crate serde;
 extern crate diesel_migrations;
 
 use actix_identity::{CookieIdentityPolicy, IdentityService};
-use actix_web::{web, App, HttpServer};
+use actix_web::{error::JsonPayloadError, guard, http::Method, middleware, web, App, HttpServer};
 use diesel::{
     r2d2::{self, ConnectionManager},
     sqlite::SqliteConnection,
 };
-use std::process;
+use std::{process, sync::Arc, time::Duration};
 
+pub mod envelope;
+pub mod error;
+pub mod filename;
 pub mod globals;
+pub mod highlight;
+pub mod listen;
+pub mod markdown;
 pub mod models;
 pub mod queries;
 pub mod routes;
 pub mod schema;
 pub mod setup;
+pub mod sniff;
+pub mod storage;
+pub mod thumbnail;
+pub mod tls;
 
 /// SQLite database connection pool
 pub type Pool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
@@ -29,7 +39,29 @@ pub type Pool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
 #[cfg(not(feature = "dev"))]
 embed_migrations!();
 
-use globals::{CONFIG, KEY};
+use globals::{CONFIG, SECRET};
+use listen::Listener;
+
+/// Periodically deletes expired entries
+async fn reaper() {
+    let mut interval = actix_rt::time::interval(Duration::from_secs(CONFIG.reap_interval));
+    loop {
+        interval.tick().await;
+        if let Err(e) = web::block(queries::reap).await {
+            eprintln!("Can't delete expired entries: {}", e);
+        }
+    }
+}
+
+/// Moves files uploaded by older versions into storage and records what they lacked, until
+/// it's done for all of them
+async fn backfill() {
+    match web::block(queries::files::backfill).await {
+        Ok(None) | Ok(Some(0)) => (),
+        Ok(Some(n)) => println!("Updated {} files uploaded by older versions", n),
+        Err(e) => eprintln!("Can't update files uploaded by older versions: {}", e),
+    }
+}
 
 #[actix_rt::main]
 async fn main() {
@@ -41,23 +73,151 @@ async fn main() {
             eprintln!("Can't prepare database: {}", e);
             process::exit(1);
         });
+        setup::run_command();
+        setup::check_legacy_password();
     }
 
-    let port = CONFIG.port;
-    println!("Listening on port {}", port);
+    // Loading syntaxes and themes takes a while and may fail, so it's done before serving
+    lazy_static::initialize(&globals::HIGHLIGHTER);
+    lazy_static::initialize(&SECRET);
+    // Otherwise the first login as an unknown user would take longer than the following ones
+    lazy_static::initialize(&globals::DUMMY_PASSWORD_HASH);
+
+    // Both write to the database, so the first cleanup waits for the backfill to be done
+    actix_rt::spawn(async {
+        backfill().await;
+        if CONFIG.reap_interval > 0 {
+            reaper().await;
+        }
+    });
+
+    let listeners = CONFIG.listeners().unwrap_or_else(|e| {
+        eprintln!("Invalid listen address: {}", e);
+        process::exit(1);
+    });
 
-    HttpServer::new(move || {
+    let tls_resolver = CONFIG.tls.as_ref().map(|tls| {
+        let resolver = Arc::new(tls::CertResolver::new(tls).unwrap_or_else(|e| {
+            eprintln!("Can't load TLS certificate: {}", e);
+            process::exit(1);
+        }));
+        #[cfg(unix)]
+        actix_rt::spawn(tls::reload_on_sighup(resolver.clone(), tls.clone()));
+        resolver
+    });
+
+    if let Some(redirect_port) = CONFIG.tls.as_ref().and_then(|tls| tls.redirect_port) {
+        let mut redirect_listeners: Vec<Listener> = Vec::new();
+        for listener in &listeners {
+            let (redirect_listener, https_port) =
+                match (listener.with_port(redirect_port), listener.port()) {
+                    (Some(l), Some(p)) => (l, routes::HttpsPort(p)),
+                    _ => continue,
+                };
+            if redirect_listeners.contains(&redirect_listener) {
+                continue;
+            }
+            // Each host redirects to the port it listens on for HTTPS, so it needs its own server
+            println!(
+                "Redirecting to HTTPS on {} (to port {})",
+                redirect_listener, https_port.0
+            );
+            let redirect_server = HttpServer::new(move || {
+                App::new()
+                    .data(https_port)
+                    .wrap(setup::logger_middleware())
+                    .default_service(web::route().to(routes::redirect_https))
+            })
+            .workers(1)
+            .bind(redirect_listener.to_string())
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "Can't bind redirect webserver to {}: {}",
+                    redirect_listener, e
+                );
+                process::exit(1);
+            });
+            actix_rt::spawn(async move {
+                if let Err(e) = redirect_server.run().await {
+                    eprintln!("Can't start redirect webserver: {}", e);
+                }
+            });
+            redirect_listeners.push(redirect_listener);
+        }
+    }
+
+    // Texts are sent as JSON, where escaping can make them up to 6 times longer
+    let json_limit = match CONFIG.limits.max_text_length {
+        0 => usize::MAX,
+        l => (l as usize).saturating_mul(6).saturating_add(64 * 1024),
+    };
+
+    let mut server = HttpServer::new(move || {
         App::new()
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(json_limit)
+                    .error_handler(|e, _| match e {
+                        JsonPayloadError::Overflow => {
+                            error::ApiError::PayloadTooLarge("Request body is too large".to_owned())
+                                .into()
+                        }
+                        e => error::ApiError::BadRequest(e.to_string()).into(),
+                    }),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|e, _| error::ApiError::BadRequest(e.to_string()).into()),
+            )
+            .app_data(
+                web::FormConfig::default()
+                    .error_handler(|e, _| error::ApiError::BadRequest(e.to_string()).into()),
+            )
+            .wrap_fn(error::middleware)
+            .wrap(middleware::DefaultHeaders::new().header("X-Content-Type-Options", "nosniff"))
             .wrap(IdentityService::new(
-                CookieIdentityPolicy::new(KEY)
+                CookieIdentityPolicy::new(&SECRET)
                     .name("filite-auth-cookie")
                     .secure(true),
             ))
             .wrap(setup::logger_middleware())
+            // The origin serving files doesn't serve anything else
+            .service(
+                web::scope("")
+                    .guard(guard::fn_guard(routes::is_usercontent))
+                    .service(
+                        web::resource("/f/{id}")
+                            .route(web::get().to(routes::files::get))
+                            .route(web::post().to(routes::files::unlock)),
+                    )
+                    .default_service(web::route().to(routes::not_found)),
+            )
             .route("/", web::get().to(routes::index))
             .route("/logout", web::get().to(routes::logout))
             .route("/config", web::get().to(routes::get_config))
             .route("/id/{id}", web::get().to(routes::id_to_str))
+            .service(
+                web::resource("/tokens")
+                    .route(web::get().to(routes::tokens::select))
+                    .route(web::post().to(routes::tokens::post)),
+            )
+            .route("/tokens/{id}", web::delete().to(routes::tokens::delete))
+            .route("/trash", web::get().to(routes::trash))
+            .service(
+                web::resource("/trash/f/{id}")
+                    .route(web::post().to(routes::files::restore))
+                    .route(web::delete().to(routes::files::purge)),
+            )
+            .service(
+                web::resource("/trash/l/{id}")
+                    .route(web::post().to(routes::links::restore))
+                    .route(web::delete().to(routes::links::purge)),
+            )
+            .service(
+                web::resource("/trash/t/{id}")
+                    .route(web::post().to(routes::texts::restore))
+                    .route(web::delete().to(routes::texts::purge)),
+            )
             .service(
                 web::resource("/f")
                     .route(web::get().to(routes::files::select))
@@ -73,33 +233,71 @@ async fn main() {
                     .route(web::get().to(routes::texts::select))
                     .route(web::post().to(routes::texts::post)),
             )
+            .service(
+                web::resource("/f/uploads")
+                    .route(web::method(Method::OPTIONS).to(routes::uploads::options))
+                    .route(web::post().to(routes::uploads::post)),
+            )
+            .service(
+                web::resource("/f/uploads/{id}")
+                    .route(web::head().to(routes::uploads::head))
+                    .route(web::patch().to(routes::uploads::patch))
+                    .route(web::delete().to(routes::uploads::delete)),
+            )
             .service(
                 web::resource("/f/{id}")
                     .route(web::get().to(routes::files::get))
+                    .route(web::post().to(routes::files::unlock))
                     .route(web::put().to(routes::files::put))
                     .route(web::delete().to(routes::files::delete)),
             )
+            .route("/f/{id}/thumb", web::get().to(routes::files::thumbnail))
             .service(
                 web::resource("/l/{id}")
                     .route(web::get().to(routes::links::get))
+                    .route(web::post().to(routes::links::unlock))
                     .route(web::put().to(routes::links::put))
                     .route(web::delete().to(routes::links::delete)),
             )
             .service(
                 web::resource("/t/{id}")
                     .route(web::get().to(routes::texts::get))
+                    .route(web::post().to(routes::texts::unlock))
                     .route(web::put().to(routes::texts::put))
                     .route(web::delete().to(routes::texts::delete)),
             )
-    })
-    .bind(&format!("localhost:{}", port))
-    .unwrap_or_else(|e| {
-        eprintln!("Can't bind webserver to specified port: {}", e);
-        process::exit(1);
-    })
-    .run()
-    .await
-    .unwrap_or_else(|e| {
+            .route("/t/{id}/revisions", web::get().to(routes::texts::revisions))
+            .route("/t/{id}/diff", web::get().to(routes::texts::diff))
+            .service(
+                web::resource("/t/{id}/{view}")
+                    .route(web::get().to(routes::texts::get_view))
+                    .route(web::post().to(routes::texts::unlock)),
+            )
+    });
+    for listener in &listeners {
+        let bound = match (listener, &tls_resolver) {
+            (Listener::Tcp(..), Some(resolver)) => {
+                println!("Listening on {} (HTTPS)", listener);
+                server.bind_rustls(listener.to_string(), tls::server_config(resolver.clone()))
+            }
+            (Listener::Tcp(..), None) => {
+                println!("Listening on {}", listener);
+                server.bind(listener.to_string())
+            }
+            #[cfg(unix)]
+            (Listener::Unix(path), _) => {
+                println!("Listening on {}", listener);
+                listen::remove_stale_socket(path)
+                    .and_then(|_| server.bind_uds(path))
+                    .and_then(|s| listen::set_socket_permissions(path, &CONFIG.socket).map(|_| s))
+            }
+        };
+        server = bound.unwrap_or_else(|e| {
+            eprintln!("Can't bind webserver to {}: {}", listener, e);
+            process::exit(1);
+        });
+    }
+    server.run().await.unwrap_or_else(|e| {
         eprintln!("Can't start webserver: {}", e);
         process::exit(1);
     });
@@ -0,0 +1,285 @@
+//! Markdown rendering using pulldown-cmark
+
+use crate::globals::HIGHLIGHTER;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use std::collections::HashSet;
+
+/// Markdown document rendered to HTML
+pub struct Rendered {
+    /// Sanitized HTML of the document
+    pub html: String,
+    /// Table of contents linking to the headings, empty if there are less than two
+    pub toc: String,
+}
+
+/// Heading of a document, listed in its table of contents
+struct Heading {
+    level: usize,
+    anchor: String,
+    text: String,
+}
+
+/// Renders Markdown to HTML, removing anything that could run scripts or alter the page
+///
+/// Headings are given anchors and fenced code blocks are highlighted using the language of
+/// their info string.
+pub fn render(contents: &str) -> Rendered {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_SMART_PUNCTUATION;
+
+    let mut events = Vec::new();
+    let mut headings = Vec::new();
+    let mut anchors = HashSet::new();
+    // Index of the start event and text of the heading being read, if any
+    let mut heading: Option<(usize, String)> = None;
+    // Language and contents of the code block being read, if any
+    let mut code: Option<(Option<String>, String)> = None;
+
+    for event in Parser::new_ext(contents, options) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => {
+                heading = Some((events.len(), String::new()));
+                events.push(event);
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                if let Some((start, text)) = heading.take() {
+                    let anchor = unique_anchor(&text, &mut anchors);
+                    if let Event::Start(Tag::Heading { id, .. }) = &mut events[start] {
+                        *id = Some(CowStr::from(anchor.clone()));
+                    }
+                    events.push(Event::InlineHtml(CowStr::from(format!(
+                        r##"<a class="anchor" href="#{}">#</a>"##,
+                        anchor
+                    ))));
+                    headings.push(Heading {
+                        level: level as usize,
+                        anchor,
+                        text,
+                    });
+                }
+                events.push(event);
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().map(str::to_owned)
+                    }
+                    CodeBlockKind::Indented => None,
+                };
+                code = Some((language, String::new()));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((language, text)) = code.take() {
+                    let highlighted = HIGHLIGHTER
+                        .highlight_classed(&text, language.as_deref())
+                        .unwrap_or_else(|e| {
+                            eprintln!("Can't highlight code block: {}", e);
+                            ammonia::clean_text(&text)
+                        });
+                    events.push(Event::Html(CowStr::from(format!(
+                        "<pre class=\"hl-code\"><code>{}</code></pre>\n",
+                        highlighted
+                    ))));
+                }
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, code)) = &mut code {
+                    code.push_str(&text);
+                }
+            }
+            Event::Text(ref text) | Event::Code(ref text) if heading.is_some() => {
+                if let Some((_, heading)) = &mut heading {
+                    heading.push_str(text);
+                }
+                events.push(event);
+            }
+            event => events.push(event),
+        }
+    }
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+    Rendered {
+        html: sanitize(&unsafe_html),
+        toc: table_of_contents(&headings),
+    }
+}
+
+/// Removes anything unsafe from HTML, keeping heading anchors and highlighting classes
+fn sanitize(html: &str) -> String {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_generic_attributes(&["class"])
+        .attribute_filter(|element, attribute, value| match attribute {
+            "class" => {
+                let allowed: Vec<&str> = value
+                    .split_whitespace()
+                    .filter(|c| c.starts_with("hl-") || (element == "a" && *c == "anchor"))
+                    .collect();
+                if allowed.is_empty() {
+                    None
+                } else {
+                    Some(allowed.join(" ").into())
+                }
+            }
+            _ => Some(value.into()),
+        });
+    for heading in &["h1", "h2", "h3", "h4", "h5", "h6"] {
+        builder.add_tag_attributes(heading, &["id"]);
+    }
+    builder.clean(html).to_string()
+}
+
+/// Returns a URL-safe anchor for a heading which isn't used by a previous one
+fn unique_anchor(text: &str, anchors: &mut HashSet<String>) -> String {
+    let mut anchor = String::new();
+    for c in text.trim().to_lowercase().chars() {
+        if c.is_alphanumeric() || c == '_' {
+            anchor.push(c);
+        } else if (c.is_whitespace() || c == '-') && !anchor.ends_with('-') {
+            anchor.push('-');
+        }
+    }
+    if anchor.is_empty() {
+        anchor.push_str("section");
+    }
+
+    let mut unique = anchor.clone();
+    let mut suffix = 1;
+    while !anchors.insert(unique.clone()) {
+        unique = format!("{}-{}", anchor, suffix);
+        suffix += 1;
+    }
+    unique
+}
+
+/// Renders nested lists linking to headings
+fn table_of_contents(headings: &[Heading]) -> String {
+    if headings.len() < 2 {
+        return String::new();
+    }
+
+    let top = headings.iter().map(|h| h.level).min().unwrap_or(1);
+    let mut toc = String::from(r#"<nav class="toc">"#);
+    let mut depth = 0;
+    for heading in headings {
+        let level = heading.level - top + 1;
+        if level > depth {
+            for _ in depth..level {
+                toc.push_str("<ul>");
+            }
+        } else {
+            toc.push_str("</li>");
+            for _ in level..depth {
+                toc.push_str("</ul></li>");
+            }
+        }
+        depth = level;
+        toc.push_str(&format!(
+            r##"<li><a href="#{}">{}</a>"##,
+            heading.anchor,
+            ammonia::clean_text(&heading.text)
+        ));
+    }
+    toc.push_str("</li>");
+    for _ in 1..depth {
+        toc.push_str("</ul></li>");
+    }
+    toc.push_str("</ul></nav>");
+    toc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_raw_html() {
+        let html =
+            render("<script>alert(1)</script>\n\nhi <img src=x onerror=alert(1)> <b>there</b>")
+                .html;
+        assert!(!html.contains("<script"), "{}", html);
+        assert!(!html.contains("alert"), "{}", html);
+        assert!(!html.contains("onerror"), "{}", html);
+        assert!(html.contains("<b>there</b>"), "{}", html);
+
+        let html = render("<iframe src=\"https://example.com\"></iframe><style>*{}</style>").html;
+        assert!(!html.contains("<iframe"), "{}", html);
+        assert!(!html.contains("<style"), "{}", html);
+    }
+
+    #[test]
+    fn removes_script_links() {
+        let html = render(
+            "[click](javascript:alert(1)) [data](data:text/html,hi) [ok](https://example.com)",
+        )
+        .html;
+        assert!(!html.contains("javascript:"), "{}", html);
+        assert!(!html.contains("data:"), "{}", html);
+        assert!(html.contains(r#"href="https://example.com""#), "{}", html);
+
+        let html = render("<a href=\"JaVaScRiPt:alert(1)\">x</a>").html;
+        assert!(!html.to_lowercase().contains("javascript:"), "{}", html);
+    }
+
+    #[test]
+    fn strips_classes_except_highlighting() {
+        let html =
+            render("<span class=\"hl-keyword evil\">x</span> <a class=\"anchor\">y</a>").html;
+        assert!(html.contains(r#"<span class="hl-keyword">"#), "{}", html);
+        assert!(!html.contains("evil"), "{}", html);
+        assert!(html.contains(r#"<a class="anchor""#), "{}", html);
+
+        let html = render("<p class=\"anchor\">z</p>").html;
+        assert!(!html.contains("class"), "{}", html);
+    }
+
+    #[test]
+    fn anchors_headings() {
+        let rendered = render(
+            "# Getting Started!\n\n## Getting started\n\n## `code` -- and  spaces\n\n## ???",
+        );
+        assert!(
+            rendered.html.contains(r#"<h1 id="getting-started">"#),
+            "{}",
+            rendered.html
+        );
+        assert!(rendered.html.contains(r#"<h2 id="getting-started-1">"#));
+        assert!(rendered.html.contains(r#"<h2 id="code-and-spaces">"#));
+        assert!(rendered.html.contains(r#"<h2 id="section">"#));
+        assert!(rendered.html.contains(
+            r##"<a class="anchor" href="#getting-started" rel="noopener noreferrer">#</a>"##
+        ));
+        assert!(rendered
+            .toc
+            .contains(r##"<a href="#getting-started-1">Getting&#32;started</a>"##));
+    }
+
+    #[test]
+    fn anchors_are_stable() {
+        let contents = "# Intro\n\n## Usage\n\n## Usage\n\n### Ünïcode Heading";
+        assert_eq!(render(contents).html, render(contents).html);
+
+        // Editing a later heading doesn't change the anchors of earlier ones
+        let edited = render("# Intro\n\n## Usage\n\n## Usage\n\n### Other");
+        assert!(edited.html.contains(r#"<h2 id="usage-1">"#));
+        assert!(render(contents)
+            .html
+            .contains(r#"<h3 id="ünïcode-heading">"#));
+    }
+
+    #[test]
+    fn lists_headings_in_a_table_of_contents() {
+        assert_eq!(render("# Only one").toc, "");
+        assert_eq!(
+            render("## A\n\n### B\n\n## <C>\n\n## ?").toc,
+            concat!(
+                r##"<nav class="toc"><ul><li><a href="#a">A</a><ul><li><a href="#b">B</a>"##,
+                r##"</li></ul></li><li><a href="#section"></a></li>"##,
+                r##"<li><a href="#section-1">?</a></li></ul></nav>"##
+            )
+        );
+    }
+}
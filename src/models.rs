@@ -9,18 +9,48 @@ pub mod files {
     pub struct File {
         /// Primary key, its radix 36 value is used as an url
         pub id: i32,
-        /// Path to the file to serve relative to the static files root
+        /// Normalized name of the file, or the path it was stored at if it isn't stored as a blob
         pub filepath: String,
         /// Creation date and time as a UNIX timestamp
         pub created: i32,
+        /// Expiration date and time as a UNIX timestamp, if any
+        pub expires: Option<i32>,
+        /// Number of views left before the entry is deleted, if limited
+        pub views_left: Option<i32>,
+        /// Username of the user who created the entry, if any
+        pub owner: Option<String>,
+        /// Custom URL-safe name the entry can be accessed with, if any
+        pub slug: Option<String>,
+        /// Hex encoded BLAKE3 digest of the contents, naming the blob they are stored in
+        pub hash: Option<String>,
+        /// Date and time the entry was moved to the trash as a UNIX timestamp, if it was
+        pub deleted_at: Option<i32>,
+        /// Hash of the password required to view the entry, if any
+        #[serde(skip_serializing)]
+        pub password_hash: Option<String>,
+        /// Name the file was uploaded with, if it was uploaded after names were normalized
+        pub original_name: Option<String>,
+        /// MIME type detected from the contents, if known
+        pub content_type: Option<String>,
+        /// Size of the contents in bytes, if known
+        pub size: Option<i64>,
     }
 
     /// A new entry to the `files` table
-    #[derive(Insertable)]
+    #[derive(Insertable, Clone)]
     #[table_name = "files"]
     pub struct NewFile<'a> {
         pub id: i32,
         pub filepath: &'a str,
+        pub expires: Option<i32>,
+        pub views_left: Option<i32>,
+        pub owner: Option<&'a str>,
+        pub slug: Option<&'a str>,
+        pub hash: Option<&'a str>,
+        pub password_hash: Option<&'a str>,
+        pub original_name: Option<&'a str>,
+        pub content_type: Option<&'a str>,
+        pub size: Option<i64>,
     }
 }
 
@@ -37,6 +67,19 @@ pub mod links {
         pub forward: String,
         /// Creation date and time as a UNIX timestamp
         pub created: i32,
+        /// Expiration date and time as a UNIX timestamp, if any
+        pub expires: Option<i32>,
+        /// Number of views left before the entry is deleted, if limited
+        pub views_left: Option<i32>,
+        /// Username of the user who created the entry, if any
+        pub owner: Option<String>,
+        /// Custom URL-safe name the entry can be accessed with, if any
+        pub slug: Option<String>,
+        /// Date and time the entry was moved to the trash as a UNIX timestamp, if it was
+        pub deleted_at: Option<i32>,
+        /// Hash of the password required to view the entry, if any
+        #[serde(skip_serializing)]
+        pub password_hash: Option<String>,
     }
 
     /// A new entry to the `links` table
@@ -45,12 +88,18 @@ pub mod links {
     pub struct NewLink<'a> {
         pub id: i32,
         pub forward: &'a str,
+        pub expires: Option<i32>,
+        pub views_left: Option<i32>,
+        pub owner: Option<&'a str>,
+        pub slug: Option<&'a str>,
+        pub password_hash: Option<&'a str>,
     }
 }
 
 /// Models from the `texts` table
 pub mod texts {
     use crate::schema::texts;
+    use std::{fmt, str::FromStr};
 
     /// An entry from the `texts` table
     #[derive(Queryable, Identifiable, Serialize)]
@@ -63,6 +112,32 @@ pub mod texts {
         pub created: i32,
         /// Whether to enable code highlighting or not for that text
         pub highlight: bool,
+        /// Expiration date and time as a UNIX timestamp, if any
+        pub expires: Option<i32>,
+        /// Number of views left before the entry is deleted, if limited
+        pub views_left: Option<i32>,
+        /// Username of the user who created the entry, if any
+        pub owner: Option<String>,
+        /// Custom URL-safe name the entry can be accessed with, if any
+        pub slug: Option<String>,
+        /// Name or file extension of the language used to highlight the text, if any
+        pub language: Option<String>,
+        /// Format of the contents, deciding how they are displayed
+        pub format: String,
+        /// Date and time the entry was moved to the trash as a UNIX timestamp, if it was
+        pub deleted_at: Option<i32>,
+        /// Hash of the password required to view the entry, if any
+        #[serde(skip_serializing)]
+        pub password_hash: Option<String>,
+        /// Username of the user who wrote the current version, if any
+        pub author: Option<String>,
+    }
+
+    impl Text {
+        /// Returns the format of the contents, treating unknown ones as plain text
+        pub fn format(&self) -> Format {
+            self.format.parse().unwrap_or(Format::Plain)
+        }
     }
 
     /// A new entry to the `texts` table
@@ -72,5 +147,292 @@ pub mod texts {
         pub id: i32,
         pub contents: &'a str,
         pub highlight: bool,
+        pub expires: Option<i32>,
+        pub views_left: Option<i32>,
+        pub owner: Option<&'a str>,
+        pub slug: Option<&'a str>,
+        pub language: Option<&'a str>,
+        pub format: &'a str,
+        pub password_hash: Option<&'a str>,
+        pub author: Option<&'a str>,
+    }
+
+    /// Format of the contents of a text
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub enum Format {
+        /// Displayed as is
+        Plain,
+        /// Displayed with syntax highlighting
+        Code,
+        /// Rendered from Markdown
+        Markdown,
+        /// Encrypted in the browser, only decrypted by the browsers of readers
+        Encrypted,
+    }
+
+    impl Format {
+        /// All existing formats
+        pub const ALL: [Format; 4] = [
+            Format::Plain,
+            Format::Code,
+            Format::Markdown,
+            Format::Encrypted,
+        ];
+
+        /// Returns the name of the format as stored in the database
+        pub fn as_str(self) -> &'static str {
+            match self {
+                Format::Plain => "plain",
+                Format::Code => "code",
+                Format::Markdown => "markdown",
+                Format::Encrypted => "encrypted",
+            }
+        }
+    }
+
+    impl FromStr for Format {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match Format::ALL.iter().find(|format| format.as_str() == s) {
+                Some(format) => Ok(*format),
+                None => Err(format!("Unknown format `{}`", s)),
+            }
+        }
+    }
+
+    impl fmt::Display for Format {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.as_str())
+        }
+    }
+}
+
+/// Models from the `text_revisions` table
+pub mod text_revisions {
+    use crate::schema::text_revisions;
+
+    /// An entry from the `text_revisions` table, holding a previous version of a text
+    #[derive(Queryable, Identifiable)]
+    pub struct TextRevision {
+        /// Primary key
+        pub id: i32,
+        /// Id of the text this is a version of
+        pub text_id: i32,
+        /// Number of the version, starting from 1 and increasing with every change
+        pub revision: i32,
+        /// Text contents
+        pub contents: String,
+        /// Format of the contents
+        pub format: String,
+        /// Name or file extension of the language used to highlight the text, if any
+        pub language: Option<String>,
+        /// Creation date and time of the version as a UNIX timestamp
+        pub created: i32,
+        /// Username of the user who wrote the version, if any
+        pub author: Option<String>,
+    }
+
+    /// A new entry to the `text_revisions` table
+    #[derive(Insertable)]
+    #[table_name = "text_revisions"]
+    pub struct NewTextRevision<'a> {
+        pub text_id: i32,
+        pub revision: i32,
+        pub contents: &'a str,
+        pub format: &'a str,
+        pub language: Option<&'a str>,
+        pub created: i32,
+        pub author: Option<&'a str>,
+    }
+}
+
+/// Models from the `tokens` table
+pub mod tokens {
+    use crate::schema::tokens;
+    use std::{fmt, str::FromStr};
+
+    /// An entry from the `tokens` table
+    #[derive(Queryable, Identifiable, Serialize)]
+    pub struct Token {
+        /// Primary key, used to revoke the token
+        pub id: i32,
+        /// Username of the user the token authenticates as
+        pub owner: String,
+        /// Name describing what the token is used for
+        pub name: String,
+        /// Hash of the token itself
+        #[serde(skip_serializing)]
+        pub token_hash: String,
+        /// Comma separated list of scopes the token grants
+        pub scopes: String,
+        /// Expiration date and time as a UNIX timestamp, if any
+        pub expires: Option<i32>,
+        /// Creation date and time as a UNIX timestamp
+        pub created: i32,
+    }
+
+    impl Token {
+        /// Whether the token grants the given scope
+        pub fn allows(&self, scope: Scope) -> bool {
+            self.scopes.split(',').any(|s| s == scope.as_str())
+        }
+    }
+
+    /// A new entry to the `tokens` table
+    #[derive(Insertable)]
+    #[table_name = "tokens"]
+    pub struct NewToken<'a> {
+        pub owner: &'a str,
+        pub name: &'a str,
+        pub token_hash: &'a str,
+        pub scopes: &'a str,
+        pub expires: Option<i32>,
+    }
+
+    /// Permission granted by a token
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub enum Scope {
+        FilesRead,
+        FilesWrite,
+        LinksRead,
+        LinksWrite,
+        TextsRead,
+        TextsWrite,
+        Delete,
+    }
+
+    impl Scope {
+        /// All existing scopes
+        pub const ALL: [Scope; 7] = [
+            Scope::FilesRead,
+            Scope::FilesWrite,
+            Scope::LinksRead,
+            Scope::LinksWrite,
+            Scope::TextsRead,
+            Scope::TextsWrite,
+            Scope::Delete,
+        ];
+
+        /// Returns the name of the scope as stored in the database
+        pub fn as_str(self) -> &'static str {
+            match self {
+                Scope::FilesRead => "files:read",
+                Scope::FilesWrite => "files:write",
+                Scope::LinksRead => "links:read",
+                Scope::LinksWrite => "links:write",
+                Scope::TextsRead => "texts:read",
+                Scope::TextsWrite => "texts:write",
+                Scope::Delete => "delete",
+            }
+        }
+
+        /// Parses a comma separated list of scopes into its normalized form
+        pub fn parse_list(list: &str) -> Result<String, String> {
+            let scopes = list
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(Scope::from_str)
+                .collect::<Result<Vec<Scope>, String>>()?;
+            if scopes.is_empty() {
+                return Err("No scopes specified".to_owned());
+            }
+
+            let names: Vec<&str> = Scope::ALL
+                .iter()
+                .filter(|s| scopes.contains(s))
+                .map(|s| s.as_str())
+                .collect();
+            Ok(names.join(","))
+        }
+    }
+
+    impl FromStr for Scope {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match Scope::ALL.iter().find(|scope| scope.as_str() == s) {
+                Some(scope) => Ok(*scope),
+                None => Err(format!("Unknown scope `{}`", s)),
+            }
+        }
+    }
+
+    impl fmt::Display for Scope {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.as_str())
+        }
+    }
+}
+
+/// Models from the `uploads` table
+pub mod uploads {
+    use crate::schema::uploads;
+
+    /// An entry from the `uploads` table, tracking a resumable upload in progress
+    #[derive(Queryable, Identifiable)]
+    pub struct Upload {
+        /// Primary key, a random string used in the upload URL
+        pub id: String,
+        /// Username of the user who created the upload
+        pub owner: String,
+        /// Total size of the file in bytes
+        pub length: i64,
+        /// Name of the file
+        pub filename: String,
+        /// Expiration date and time of the resulting entry as a UNIX timestamp, if any
+        pub expires: Option<i32>,
+        /// Number of views the resulting entry is limited to, if any
+        pub views_left: Option<i32>,
+        /// Slug of the resulting entry, if any
+        pub slug: Option<String>,
+        /// Creation date and time as a UNIX timestamp
+        pub created: i32,
+        /// Hash of the password required to view the resulting entry, if any
+        pub password_hash: Option<String>,
+    }
+
+    /// A new entry to the `uploads` table
+    #[derive(Insertable)]
+    #[table_name = "uploads"]
+    pub struct NewUpload<'a> {
+        pub id: &'a str,
+        pub owner: &'a str,
+        pub length: i64,
+        pub filename: &'a str,
+        pub expires: Option<i32>,
+        pub views_left: Option<i32>,
+        pub slug: Option<&'a str>,
+        pub password_hash: Option<&'a str>,
+    }
+}
+
+/// Models from the `users` table
+pub mod users {
+    use crate::schema::users;
+
+    /// An entry from the `users` table
+    #[derive(Queryable, Identifiable)]
+    pub struct User {
+        /// Primary key
+        pub id: i32,
+        /// Unique name used to log in
+        pub username: String,
+        /// Hash of the user's password
+        pub password_hash: String,
+        /// Whether the user can see and modify all entries
+        pub admin: bool,
+        /// Creation date and time as a UNIX timestamp
+        pub created: i32,
+    }
+
+    /// A new entry to the `users` table
+    #[derive(Insertable)]
+    #[table_name = "users"]
+    pub struct NewUser<'a> {
+        pub username: &'a str,
+        pub password_hash: &'a str,
+        pub admin: bool,
     }
 }
@@ -21,6 +21,8 @@ pub struct SelectFilters {
     pub limit: Option<i64>,
     /// Whether to sort the results in ascending order
     pub asc: bool,
+    /// Only select entries owned by this user
+    pub owner: Option<String>,
 }
 
 impl From<SelectQuery> for SelectFilters {
@@ -29,6 +31,7 @@ impl From<SelectQuery> for SelectFilters {
             range: (query.from, query.to),
             limit: query.limit,
             asc: query.asc.unwrap_or(false),
+            owner: None,
         }
     }
 }
@@ -36,12 +39,16 @@ impl From<SelectQuery> for SelectFilters {
 /// Code common to all select functions
 macro_rules! common_select {
     ($q:expr, $f:expr) => {
+        $q = $q.filter(deleted_at.is_null());
         if let Some(from) = $f.range.0 {
             $q = $q.filter(created.ge(from));
         }
         if let Some(to) = $f.range.1 {
             $q = $q.filter(created.lt(to));
         }
+        if let Some(o) = &$f.owner {
+            $q = $q.filter(owner.eq(o.as_str()));
+        }
         if let Some(limit) = $f.limit {
             $q = $q.limit(limit);
         }
@@ -63,10 +70,140 @@ macro_rules! find {
     };
 }
 
-/// DELETE an entry
-macro_rules! delete {
+/// SELECT the id of the entry a slug or a base-36 id refers to
+///
+/// Slugs take precedence over ids, which are kept for backwards compatibility.
+macro_rules! lookup {
+    ($n:ident) => {
+        pub fn lookup(l_key: &str) -> diesel::result::QueryResult<Option<i32>> {
+            let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
+            let by_slug = $n
+                .select(id)
+                .filter(slug.eq(l_key))
+                .first::<i32>(conn)
+                .optional()?;
+            if by_slug.is_some() {
+                return Ok(by_slug);
+            }
+
+            match crate::queries::parse_id(l_key) {
+                Some(l_id) => $n.select(id).find(l_id).first::<i32>(conn).optional(),
+                None => Ok(None),
+            }
+        }
+    };
+}
+
+/// SELECT the password hash of an entry given its id
+macro_rules! find_password {
+    ($n:ident) => {
+        pub fn find_password(f_id: i32) -> diesel::result::QueryResult<Option<String>> {
+            let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
+            $n.find(f_id)
+                .filter(deleted_at.is_null())
+                .select(password_hash)
+                .first::<Option<String>>(conn)
+        }
+    };
+}
+
+/// SELECT a single entry which can be viewed given its id, without counting a view
+///
+/// Lets requests which don't send the entry, such as conditional ones, be answered first.
+macro_rules! find_viewable {
     ($n:ident, $t:ty) => {
+        pub fn find_viewable(f_id: i32) -> diesel::result::QueryResult<$t> {
+            let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
+            $n.find(f_id)
+                .filter(deleted_at.is_null())
+                .filter(views_left.is_null().or(views_left.gt(0)))
+                .first::<$t>(conn)
+        }
+    };
+}
+
+/// SELECT a single entry given its id and count it as a view
+///
+/// Entries in the trash or without views left can't be viewed. Entries with a view limit are deleted once they reach zero views left.
+macro_rules! view {
+    ($n:ident, $t:ty) => {
+        pub fn view(v_id: i32) -> diesel::result::QueryResult<$t> {
+            let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
+            view_in(conn, v_id)
+        }
+
+        fn view_in(conn: &SqliteConnection, v_id: i32) -> diesel::result::QueryResult<$t> {
+            conn.immediate_transaction(|| {
+                let mut entry = $n
+                    .find(v_id)
+                    .filter(deleted_at.is_null())
+                    .first::<$t>(conn)?;
+                if crate::queries::is_expired(entry.expires) {
+                    return Ok(entry);
+                }
+
+                match entry.views_left {
+                    Some(v) if v <= 0 => return Err(diesel::result::Error::NotFound),
+                    Some(1) => {
+                        diesel::delete($n.find(v_id)).execute(conn)?;
+                        entry.views_left = Some(0);
+                    }
+                    Some(v) => {
+                        diesel::update($n.find(v_id))
+                            .set(views_left.eq(v - 1))
+                            .execute(conn)?;
+                        entry.views_left = Some(v - 1);
+                    }
+                    None => (),
+                }
+                Ok(entry)
+            })
+        }
+    };
+}
+
+/// SELECT the owner and slug of an entry given its id, if the entry exists
+///
+/// Replacing an entry keeps its owner, even when an admin replaces it, and its slug unless it's given a new one.
+macro_rules! existing {
+    ($n:ident) => {
+        fn existing(
+            conn: &SqliteConnection,
+            e_id: i32,
+        ) -> diesel::result::QueryResult<Option<(Option<String>, Option<String>)>> {
+            $n.find(e_id)
+                .select((owner, slug))
+                .first::<(Option<String>, Option<String>)>(conn)
+                .optional()
+        }
+    };
+}
+
+/// Move an entry to the trash, or DELETE it right away if the trash is disabled
+macro_rules! delete {
+    ($n:ident) => {
         pub fn delete(d_id: i32) -> diesel::result::QueryResult<()> {
+            if crate::globals::CONFIG.trash_retention == 0 {
+                return purge(d_id);
+            }
+
+            let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
+            let now = chrono::Utc::now().timestamp() as i32;
+            let updated = diesel::update($n.find(d_id).filter(deleted_at.is_null()))
+                .set(deleted_at.eq(now))
+                .execute(conn)?;
+            match updated {
+                0 => Err(diesel::result::Error::NotFound),
+                _ => Ok(()),
+            }
+        }
+    };
+}
+
+/// DELETE an entry, whether it's in the trash or not
+macro_rules! purge {
+    ($n:ident, $t:ty) => {
+        pub fn purge(d_id: i32) -> diesel::result::QueryResult<()> {
             let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
             diesel::delete(&$n.find(d_id).first::<$t>(conn)?).execute(conn)?;
             Ok(())
@@ -74,21 +211,173 @@ macro_rules! delete {
     };
 }
 
+/// Restore, SELECT and DELETE entries in the trash
+macro_rules! trash {
+    ($n:ident, $t:ty) => {
+        /// Take an entry out of the trash
+        pub fn restore(r_id: i32) -> diesel::result::QueryResult<()> {
+            let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
+            let updated = diesel::update($n.find(r_id).filter(deleted_at.is_not_null()))
+                .set(deleted_at.eq(None::<i32>))
+                .execute(conn)?;
+            match updated {
+                0 => Err(diesel::result::Error::NotFound),
+                _ => Ok(()),
+            }
+        }
+
+        /// SELECT the entries in the trash, most recently deleted first
+        pub fn trashed(t_owner: Option<&str>) -> diesel::result::QueryResult<Vec<$t>> {
+            let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
+            let mut query = $n.filter(deleted_at.is_not_null()).into_boxed();
+            if let Some(o) = t_owner {
+                query = query.filter(owner.eq(o));
+            }
+            query.order(deleted_at.desc()).load::<$t>(conn)
+        }
+
+        /// DELETE the entries moved to the trash before a given date
+        pub fn reap_trash(before: i32) -> diesel::result::QueryResult<usize> {
+            let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
+            let trashed = $n
+                .select(id)
+                .filter(deleted_at.le(before))
+                .load::<i32>(conn)?;
+            for t_id in &trashed {
+                purge(*t_id)?;
+            }
+            Ok(trashed.len())
+        }
+    };
+}
+
+/// DELETE all expired entries
+macro_rules! reap {
+    ($n:ident) => {
+        pub fn reap(now: i32) -> diesel::result::QueryResult<usize> {
+            let conn: &SqliteConnection = &crate::globals::POOL.get().unwrap();
+            diesel::delete($n.filter(expires.le(now))).execute(conn)
+        }
+    };
+}
+
+/// Parse a key as an id if it's written the way ids are, in lowercase base-36
+///
+/// Keys with a sign, leading zeros or uppercase letters are slugs instead.
+pub fn parse_id(key: &str) -> Option<i32> {
+    let p_id = i32::from_str_radix(key, 36).ok().filter(|i| *i >= 0)?;
+    if format!("{}", radix_fmt::radix_36(p_id)) == key {
+        Some(p_id)
+    } else {
+        None
+    }
+}
+
+/// Whether an expiration timestamp is in the past
+pub fn is_expired(expires: Option<i32>) -> bool {
+    match expires {
+        Some(e) => i64::from(e) <= chrono::Utc::now().timestamp(),
+        None => false,
+    }
+}
+
+/// DELETE expired entries and entries past their trash retention from all tables,
+/// returning how many were removed
+pub fn reap() -> diesel::result::QueryResult<usize> {
+    let now = chrono::Utc::now().timestamp() as i32;
+    let mut reaped =
+        files::reap(now)? + links::reap(now)? + texts::reap(now)? + uploads::reap(now)?;
+
+    let retention = crate::globals::CONFIG.trash_retention;
+    if retention > 0 {
+        let before = now.saturating_sub(retention.min(i32::MAX as u64) as i32);
+        reaped +=
+            files::reap_trash(before)? + links::reap_trash(before)? + texts::reap_trash(before)?;
+    }
+    Ok(reaped)
+}
+
+/// Connection to a new in-memory database
+#[cfg(all(test, not(feature = "dev")))]
+fn test_database() -> diesel::sqlite::SqliteConnection {
+    use diesel::Connection;
+
+    let conn = diesel::sqlite::SqliteConnection::establish(":memory:").unwrap();
+    crate::embedded_migrations::run(&conn).unwrap();
+    conn
+}
+
 /// Queries affecting the `files` table
 pub mod files {
     use crate::{
-        globals::{CONFIG, POOL},
+        globals::{CONFIG, POOL, STORAGE},
         models::files::*,
         queries::SelectFilters,
-        schema::files::{dsl::*, table},
+        schema::{
+            blobs, completed_tasks,
+            files::{dsl::*, table},
+        },
+        sniff,
+        storage::Storage,
+        thumbnail,
     };
     use diesel::{
+        dsl::sql,
         prelude::*,
         result::{DatabaseErrorKind, Error, QueryResult},
+        sql_types::BigInt,
+    };
+    use std::{
+        fs, io,
+        path::{Component, Path, PathBuf},
+        sync::{Condvar, Mutex},
     };
-    use std::fs;
 
     find!(files, File);
+    lookup!(files);
+    find_password!(files);
+    find_viewable!(files, File);
+    delete!(files);
+    trash!(files, File);
+    existing!(files);
+
+    /// SELECT a single file entry given its id and count it as a view
+    ///
+    /// Entries in the trash can't be viewed. The last view of an entry with a view limit deletes
+    /// it along with its file, so the file is opened with `open` beforehand and can still be
+    /// served. What `open` returned is given back if it was called.
+    pub fn view<T>(v_id: i32, open: impl FnOnce(&File) -> T) -> QueryResult<(File, Option<T>)> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        let (file, opened, orphan) = conn.immediate_transaction(|| {
+            let mut file = files
+                .find(v_id)
+                .filter(deleted_at.is_null())
+                .first::<File>(conn)?;
+            if crate::queries::is_expired(file.expires) {
+                return Ok((file, None, None));
+            }
+
+            match file.views_left {
+                Some(v) if v <= 0 => Err(Error::NotFound),
+                Some(1) => {
+                    let opened = open(&file);
+                    let orphan = delete_entry(conn, v_id)?;
+                    file.views_left = Some(0);
+                    Ok((file, Some(opened), orphan))
+                }
+                Some(v) => {
+                    diesel::update(files.find(v_id))
+                        .set(views_left.eq(v - 1))
+                        .execute(conn)?;
+                    file.views_left = Some(v - 1);
+                    Ok((file, None, None))
+                }
+                None => Ok((file, None, None)),
+            }
+        })?;
+        remove_orphan(conn, orphan);
+        Ok((file, opened))
+    }
 
     /// SELECT multiple file entries
     pub fn select(filters: SelectFilters) -> QueryResult<Vec<File>> {
@@ -98,53 +387,698 @@ pub mod files {
         query.load::<File>(conn)
     }
 
-    /// Delete an existing file on disk
-    fn fs_del(fid: i32) -> QueryResult<()> {
-        let mut path = CONFIG.files_dir.clone();
-        path.push(match find(fid) {
-            Ok(f) => f.filepath,
-            Err(e) => {
-                return match e {
-                    Error::NotFound => Ok(()),
-                    _ => Err(e),
-                }
+    /// Error storing a file entry
+    #[derive(Debug)]
+    pub enum StoreError {
+        /// Storing the entry would exceed a storage quota, described by the message
+        QuotaExceeded(&'static str),
+        /// The query failed
+        Query(Error),
+    }
+
+    impl From<Error> for StoreError {
+        fn from(error: Error) -> Self {
+            StoreError::Query(error)
+        }
+    }
+
+    /// Returns the total size of file entries in bytes, only counting those of a user if given
+    /// and leaving out an entry which is being replaced
+    fn sum_sizes(
+        conn: &SqliteConnection,
+        s_owner: Option<&str>,
+        except: Option<i32>,
+    ) -> QueryResult<u64> {
+        let mut query = files
+            .select(sql::<BigInt>("COALESCE(SUM(size), 0)"))
+            .into_boxed();
+        if let Some(o) = s_owner {
+            query = query.filter(owner.eq(o));
+        }
+        if let Some(e) = except {
+            query = query.filter(id.ne(e));
+        }
+        query.first::<i64>(conn).map(|s| s as u64)
+    }
+
+    /// Returns the total size of file entries in bytes, only counting those of a user if given
+    ///
+    /// Entries in the trash are counted since their contents are still stored.
+    pub fn used_storage(s_owner: Option<&str>) -> QueryResult<u64> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        sum_sizes(conn, s_owner, None)
+    }
+
+    /// Checks that storing a new file entry stays within the storage quotas
+    ///
+    /// The previous size of the entry being replaced doesn't count, since its contents are replaced too.
+    fn check_quota(conn: &SqliteConnection, new_file: &NewFile) -> Result<(), StoreError> {
+        let limits = &CONFIG.limits;
+        let q_size = new_file.size.unwrap_or(0) as u64;
+        if limits.quota > 0 && sum_sizes(conn, None, Some(new_file.id))? + q_size > limits.quota {
+            return Err(StoreError::QuotaExceeded("Storage quota exceeded"));
+        }
+        if let (true, Some(o)) = (limits.user_quota > 0, new_file.owner) {
+            if sum_sizes(conn, Some(o), Some(new_file.id))? + q_size > limits.user_quota {
+                return Err(StoreError::QuotaExceeded("User storage quota exceeded"));
             }
-        });
-        if !path.exists() {
-            return Ok(());
         }
+        Ok(())
+    }
 
-        fs::remove_file(path).map_err(|e| {
-            Error::DatabaseError(
-                DatabaseErrorKind::UnableToSendCommand,
-                Box::new(format!("{}", e)),
-            )
-        })
+    /// Returns the storage key of the blob with the given hash
+    pub fn blob_key(b_hash: &str) -> String {
+        format!("blobs/{}", b_hash)
+    }
+
+    /// Returns the storage key of a thumbnail of the blob with the given hash
+    pub fn thumbnail_key(b_hash: &str, t_size: u32) -> String {
+        format!("{}.thumb-{}.png", blob_key(b_hash), t_size)
     }
 
-    /// REPLACE a file entry
-    pub fn replace(r_id: i32, r_filepath: &str) -> QueryResult<File> {
-        fs_del(r_id)?;
+    /// Returns the storage key of a thumbnail of a file entry stored before blobs were introduced
+    ///
+    /// These files aren't shared between entries, so their thumbnails are named after the entry.
+    pub fn legacy_thumbnail_key(t_id: i32, t_size: u32) -> String {
+        format!("thumbs/{}-{}.png", radix_fmt::radix_36(t_id), t_size)
+    }
 
-        let conn: &SqliteConnection = &POOL.get().unwrap();
-        let new_file = NewFile {
-            id: r_id,
-            filepath: r_filepath,
+    /// Returns the local path of a file entry stored before blobs were introduced
+    pub fn legacy_path(file: &File) -> Option<PathBuf> {
+        if file.hash.is_some() {
+            return None;
+        }
+        resolve_legacy_path(&CONFIG.files_dir, &file.filepath)
+    }
+
+    /// Resolves the path a file was stored at before blobs were introduced
+    ///
+    /// Older versions stored absolute paths under the static files root, and paths relative to
+    /// it before that. Paths which could point outside of it are never returned.
+    fn resolve_legacy_path(files_dir: &Path, l_path: &str) -> Option<PathBuf> {
+        let path = Path::new(l_path);
+        let relative = if path.is_absolute() {
+            path.strip_prefix(files_dir).ok()?
+        } else {
+            path
         };
-        diesel::replace_into(table)
-            .values(&new_file)
+        let mut components = relative.components().peekable();
+        if components.peek().is_some() && components.all(|c| matches!(c, Component::Normal(_))) {
+            Some(files_dir.join(relative))
+        } else {
+            None
+        }
+    }
+
+    /// Contents left behind by a deleted entry
+    enum Orphan {
+        /// Hash of a blob which isn't referenced anymore
+        Blob(String),
+        /// Id and path of a file entry stored before blobs were introduced
+        Legacy(i32, PathBuf),
+    }
+
+    /// Converts a storage error to a query error
+    fn storage_error(e: io::Error) -> Error {
+        Error::DatabaseError(
+            DatabaseErrorKind::UnableToSendCommand,
+            Box::new(format!("{}", e)),
+        )
+    }
+
+    /// Hashes of the blobs being stored or deleted
+    static BUSY_BLOBS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    /// Notified whenever a blob stops being busy
+    static BLOB_RELEASED: Condvar = Condvar::new();
+
+    /// Keeps a blob from being stored and deleted at the same time, until it's dropped
+    ///
+    /// Storage requests can take a while, so they are made outside of transactions. A blob is
+    /// held from being stored until the reference to it is committed, and from checking that it
+    /// isn't referenced anymore until it's deleted, so one can't happen in the middle of the other.
+    struct BlobGuard(String);
+
+    impl BlobGuard {
+        /// Waits until a blob isn't busy anymore and holds it
+        fn hold(b_hash: &str) -> Self {
+            let mut busy = BUSY_BLOBS.lock().unwrap_or_else(|e| e.into_inner());
+            while busy.iter().any(|h| h == b_hash) {
+                busy = BLOB_RELEASED.wait(busy).unwrap_or_else(|e| e.into_inner());
+            }
+            busy.push(b_hash.to_owned());
+            BlobGuard(b_hash.to_owned())
+        }
+    }
+
+    impl Drop for BlobGuard {
+        fn drop(&mut self) {
+            let mut busy = BUSY_BLOBS.lock().unwrap_or_else(|e| e.into_inner());
+            busy.retain(|h| *h != self.0);
+            BLOB_RELEASED.notify_all();
+        }
+    }
+
+    /// Stores the staged file as a blob unless it's already stored, consuming the staged file
+    ///
+    /// The blob should be held until a reference to it is committed.
+    fn store_blob(storage: &dyn Storage, b_hash: &str, staged: &Path) -> io::Result<()> {
+        let key = blob_key(b_hash);
+        if storage.stat(&key)?.is_some() {
+            fs::remove_file(staged)
+        } else {
+            storage.put(&key, staged)
+        }
+    }
+
+    /// Whether a blob is referenced by any entry
+    fn is_referenced(conn: &SqliteConnection, b_hash: &str) -> QueryResult<bool> {
+        blobs::table
+            .find(b_hash)
+            .select(blobs::hash)
+            .first::<String>(conn)
+            .optional()
+            .map(|b| b.is_some())
+    }
+
+    /// Deletes a blob and its thumbnails unless it's referenced again
+    ///
+    /// The blob should be held while it's deleted.
+    fn delete_blob(
+        conn: &SqliteConnection,
+        storage: &dyn Storage,
+        b_hash: &str,
+    ) -> QueryResult<()> {
+        if is_referenced(conn, b_hash)? {
+            return Ok(());
+        }
+        for t_size in thumbnail::SIZES {
+            storage
+                .delete(&thumbnail_key(b_hash, *t_size))
+                .map_err(storage_error)?;
+        }
+        storage.delete(&blob_key(b_hash)).map_err(storage_error)
+    }
+
+    /// Adds a reference to a blob which was stored beforehand
+    fn acquire_blob(conn: &SqliteConnection, b_hash: &str) -> QueryResult<()> {
+        let updated = diesel::update(blobs::table.find(b_hash))
+            .set(blobs::refcount.eq(blobs::refcount + 1))
             .execute(conn)?;
-        find(r_id)
+        if updated == 0 {
+            diesel::insert_into(blobs::table)
+                .values((blobs::hash.eq(b_hash), blobs::refcount.eq(1)))
+                .execute(conn)?;
+        }
+        Ok(())
     }
 
-    /// DELETE an entry
-    pub fn delete(d_id: i32) -> QueryResult<()> {
-        fs_del(d_id)?;
+    /// Removes a reference to a blob, returning it if it isn't referenced anymore
+    fn release_blob(conn: &SqliteConnection, b_hash: &str) -> QueryResult<Option<Orphan>> {
+        diesel::update(blobs::table.find(b_hash))
+            .set(blobs::refcount.eq(blobs::refcount - 1))
+            .execute(conn)?;
+        let refcount = blobs::table
+            .find(b_hash)
+            .select(blobs::refcount)
+            .first::<i32>(conn)
+            .optional()?;
+        match refcount {
+            Some(r) if r > 0 => Ok(None),
+            _ => {
+                diesel::delete(blobs::table.find(b_hash)).execute(conn)?;
+                Ok(Some(Orphan::Blob(b_hash.to_owned())))
+            }
+        }
+    }
 
+    /// Releases the contents of an entry, returning them if they should be deleted
+    ///
+    /// Contents are only deleted once the transaction releasing them is committed. The
+    /// thumbnails of a blob or legacy file are deleted along with it.
+    fn fs_del(conn: &SqliteConnection, fid: i32) -> QueryResult<Option<Orphan>> {
+        let file = match files.find(fid).first::<File>(conn) {
+            Ok(f) => f,
+            Err(Error::NotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match &file.hash {
+            Some(h) => release_blob(conn, h),
+            None => Ok(legacy_path(&file).map(|path| Orphan::Legacy(file.id, path))),
+        }
+    }
+
+    /// Deletes a file stored before blobs were introduced along with its thumbnails
+    fn remove_legacy(storage: &dyn Storage, l_id: i32, path: &Path) -> io::Result<()> {
+        for t_size in thumbnail::SIZES {
+            storage.delete(&legacy_thumbnail_key(l_id, *t_size))?;
+        }
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes contents left behind by an entry, after the transaction releasing them is committed
+    ///
+    /// The entry is already gone by then, so contents which can't be deleted are only reported.
+    fn remove_orphan(conn: &SqliteConnection, orphan: Option<Orphan>) {
+        let removed = match &orphan {
+            Some(Orphan::Blob(b_hash)) => {
+                let _guard = BlobGuard::hold(b_hash);
+                delete_blob(conn, &**STORAGE, b_hash)
+            }
+            Some(Orphan::Legacy(l_id, path)) => {
+                remove_legacy(&**STORAGE, *l_id, path).map_err(storage_error)
+            }
+            None => Ok(()),
+        };
+        if let Err(e) = removed {
+            eprintln!("Can't delete contents of a deleted file: {}", e);
+        }
+    }
+
+    /// Gives a new file entry the owner and slug of the entry it replaces
+    fn keep_existing<'a>(
+        new_file: NewFile<'a>,
+        e_entry: &'a Option<(Option<String>, Option<String>)>,
+    ) -> NewFile<'a> {
+        match e_entry {
+            Some((e_owner, e_slug)) => NewFile {
+                owner: e_owner.as_deref(),
+                slug: new_file.slug.or(e_slug.as_deref()),
+                ..new_file
+            },
+            None => new_file,
+        }
+    }
+
+    /// REPLACE a file entry, storing the staged file as a blob named after its hash
+    ///
+    /// The blob is stored before the transaction so the database isn't locked while it's
+    /// uploaded, and released contents are deleted after it's committed.
+    pub fn replace(new_file: NewFile, staged: &Path) -> Result<File, StoreError> {
         let conn: &SqliteConnection = &POOL.get().unwrap();
+        // Checked beforehand so the contents aren't stored for nothing
+        let e_entry = existing(conn, new_file.id)?;
+        check_quota(conn, &keep_existing(new_file.clone(), &e_entry))?;
+
+        let r_id = new_file.id;
+        let guard = new_file.hash.map(BlobGuard::hold);
+        if let Some(h) = new_file.hash {
+            store_blob(&**STORAGE, h, staged).map_err(storage_error)?;
+        }
+        let result = conn.immediate_transaction(|| {
+            let e_entry = existing(conn, r_id)?;
+            let new_file = keep_existing(new_file, &e_entry);
+            // Checked again in the same transaction as the insert so concurrent uploads can't
+            // exceed it
+            check_quota(conn, &new_file)?;
+            // Acquire the new blob first so replacing an entry with the same contents keeps it
+            if let Some(h) = new_file.hash {
+                acquire_blob(conn, h)?;
+            }
+            let orphan = fs_del(conn, r_id)?;
+            diesel::replace_into(table)
+                .values(&new_file)
+                .execute(conn)?;
+            Ok::<_, StoreError>(orphan)
+        });
+        if let (Err(_), Some(guard)) = (&result, &guard) {
+            if let Err(e) = delete_blob(conn, &**STORAGE, &guard.0) {
+                eprintln!("Can't delete unused blob: {}", e);
+            }
+        }
+        // Released before deleting orphans, which may have to hold other blobs
+        drop(guard);
+        remove_orphan(conn, result?);
+        Ok(files.find(r_id).first::<File>(conn)?)
+    }
+
+    /// DELETE an entry as part of a transaction, returning its contents if they should be deleted
+    /// once it's committed
+    fn delete_entry(conn: &SqliteConnection, d_id: i32) -> QueryResult<Option<Orphan>> {
+        let orphan = fs_del(conn, d_id)?;
         diesel::delete(&files.find(d_id).first::<File>(conn)?).execute(conn)?;
+        Ok(orphan)
+    }
+
+    /// DELETE an entry along with its file, whether it's in the trash or not
+    pub fn purge(d_id: i32) -> QueryResult<()> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        let orphan = conn.immediate_transaction(|| delete_entry(conn, d_id))?;
+        remove_orphan(conn, orphan);
         Ok(())
     }
+
+    /// Returns the MIME type and size of the contents of a blob, if it's stored
+    fn describe_blob(
+        storage: &dyn Storage,
+        b_hash: &str,
+        name: &str,
+    ) -> io::Result<Option<(String, i64)>> {
+        let key = blob_key(b_hash);
+        let stat = match storage.stat(&key)? {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let head = sniff::read_head(storage.get(&key)?)?;
+        Ok(Some((sniff::content_type(&head, name), stat.size as i64)))
+    }
+
+    /// Returns the name a file stored before blobs were introduced was uploaded with
+    ///
+    /// Older versions prefixed names with the base-36 time of the upload, which is only removed
+    /// if it matches the creation time of the entry, since it may be part of the name otherwise.
+    fn legacy_name(file: &File, path: &Path) -> String {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match name.split_once('.') {
+            Some((prefix, rest)) if !rest.is_empty() => match i64::from_str_radix(prefix, 36) {
+                Ok(t) if (t - i64::from(file.created)).abs() <= LEGACY_NAME_TOLERANCE => {
+                    rest.to_owned()
+                }
+                _ => name,
+            },
+            _ => name,
+        }
+    }
+
+    /// Maximum difference in seconds between the time in the name of a legacy file and the
+    /// creation of its entry, since it was taken before the upload
+    const LEGACY_NAME_TOLERANCE: i64 = 24 * 60 * 60;
+
+    /// Moves the contents of a file entry stored before blobs were introduced into a blob,
+    /// recording their hash, original name, MIME type and size, and returns whether it was done
+    ///
+    /// The legacy file is copied and only deleted once the entry refers to the blob.
+    fn adopt_legacy(
+        conn: &SqliteConnection,
+        files_dir: &Path,
+        storage: &dyn Storage,
+        file: &File,
+        path: &Path,
+    ) -> QueryResult<bool> {
+        let mut hasher = blake3::Hasher::new();
+        fs::File::open(path)
+            .and_then(|mut f| io::copy(&mut f, &mut hasher))
+            .map_err(storage_error)?;
+        let b_hash = hasher.finalize().to_hex().to_string();
+        let b_name = file
+            .original_name
+            .clone()
+            .unwrap_or_else(|| legacy_name(file, path));
+        let b_filepath = crate::filename::sanitize(&b_name);
+        let (b_type, b_size) = sniff::describe(path, &b_filepath).map_err(storage_error)?;
+
+        let staging_dir = files_dir.join(".tmp");
+        let staged = staging_dir.join(format!("legacy-{}", radix_fmt::radix_36(file.id)));
+        let guard = BlobGuard::hold(&b_hash);
+        let stored = fs::create_dir_all(&staging_dir)
+            .and_then(|_| fs::copy(path, &staged))
+            .and_then(|_| store_blob(storage, &b_hash, &staged));
+        if let Err(e) = stored {
+            let _ = fs::remove_file(&staged);
+            return Err(storage_error(e));
+        }
+        let updated = conn.immediate_transaction(|| {
+            let updated = diesel::update(files.find(file.id).filter(hash.is_null()))
+                .set((
+                    hash.eq(&b_hash),
+                    filepath.eq(&b_filepath),
+                    original_name.eq(&b_name),
+                    content_type.eq(&b_type),
+                    size.eq(b_size),
+                ))
+                .execute(conn)?;
+            if updated > 0 {
+                acquire_blob(conn, &b_hash)?;
+            }
+            Ok::<_, Error>(updated > 0)
+        });
+        if !matches!(updated, Ok(true)) {
+            delete_blob(conn, storage, &b_hash)?;
+        }
+        drop(guard);
+
+        if updated? {
+            if let Err(e) = remove_legacy(storage, file.id, path) {
+                eprintln!("Can't delete legacy file {}: {}", path.display(), e);
+            }
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Name under which the backfill is recorded once it's complete
+    const BACKFILL_TASK: &str = "backfill_file_types";
+
+    /// Whether a file entry lacks anything the backfill records
+    macro_rules! needs_backfill {
+        () => {
+            hash.is_null().or(size.is_null())
+        };
+    }
+
+    /// UPDATE file entries created before their hash, original name, MIME type and size were
+    /// recorded, returning how many were updated or `None` if that was already done
+    ///
+    /// Files stored before blobs were introduced are moved into blobs. The backfill is run again
+    /// on the next start until every entry could be updated.
+    pub fn backfill() -> QueryResult<Option<usize>> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        backfill_in(conn, &CONFIG.files_dir, &**STORAGE)
+    }
+
+    /// Runs the backfill against the given static files root and storage backend
+    fn backfill_in(
+        conn: &SqliteConnection,
+        files_dir: &Path,
+        storage: &dyn Storage,
+    ) -> QueryResult<Option<usize>> {
+        let completed = completed_tasks::table
+            .find(BACKFILL_TASK)
+            .select(completed_tasks::name)
+            .first::<String>(conn)
+            .optional()?;
+        if completed.is_some() {
+            return Ok(None);
+        }
+
+        let missing = files.filter(needs_backfill!()).load::<File>(conn)?;
+        let mut backfilled = 0;
+        for file in &missing {
+            let result = match &file.hash {
+                Some(h) => match describe_blob(storage, h, &file.filepath) {
+                    Ok(Some((b_type, b_size))) => diesel::update(files.find(file.id))
+                        .set((content_type.eq(b_type), size.eq(b_size)))
+                        .execute(conn)
+                        .map(|_| true),
+                    Ok(None) => Ok(false),
+                    Err(e) => Err(storage_error(e)),
+                },
+                None => match resolve_legacy_path(files_dir, &file.filepath) {
+                    Some(path) if path.exists() => {
+                        adopt_legacy(conn, files_dir, storage, file, &path)
+                    }
+                    _ => Ok(false),
+                },
+            };
+            match result {
+                Ok(true) => backfilled += 1,
+                Ok(false) => eprintln!("Can't find contents of file {}", file.id),
+                Err(e) => eprintln!("Can't backfill file {}: {}", file.id, e),
+            }
+        }
+
+        let remaining = files
+            .filter(needs_backfill!())
+            .count()
+            .get_result::<i64>(conn)?;
+        if remaining == 0 {
+            diesel::insert_into(completed_tasks::table)
+                .values((
+                    completed_tasks::name.eq(BACKFILL_TASK),
+                    completed_tasks::completed.eq(chrono::Utc::now().timestamp() as i32),
+                ))
+                .execute(conn)?;
+        }
+        Ok(Some(backfilled))
+    }
+
+    /// DELETE all expired entries along with their files on disk
+    ///
+    /// Entries without views left, which older versions could leave behind, are deleted too.
+    pub fn reap(now: i32) -> QueryResult<usize> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        let expired = files
+            .select(id)
+            .filter(expires.le(now))
+            .or_filter(views_left.le(0))
+            .load::<i32>(conn)?;
+        for e_id in &expired {
+            purge(*e_id)?;
+        }
+        Ok(expired.len())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        #[cfg(not(feature = "dev"))]
+        use crate::setup::StorageConfig;
+
+        #[test]
+        fn resolves_legacy_paths() {
+            let files_dir = Path::new("/var/lib/filite/files");
+            // Stored by versions which kept the absolute path of uploads
+            assert_eq!(
+                resolve_legacy_path(files_dir, "/var/lib/filite/files/pzw3k1.photo.png"),
+                Some(files_dir.join("pzw3k1.photo.png"))
+            );
+            // Stored by versions which kept paths relative to the static files root
+            assert_eq!(
+                resolve_legacy_path(files_dir, "pzw3k1.notes.txt"),
+                Some(files_dir.join("pzw3k1.notes.txt"))
+            );
+            assert_eq!(
+                resolve_legacy_path(files_dir, "nested/file.txt"),
+                Some(files_dir.join("nested/file.txt"))
+            );
+        }
+
+        #[test]
+        fn rejects_legacy_paths_outside_of_the_files_dir() {
+            let files_dir = Path::new("/var/lib/filite/files");
+            for l_path in &[
+                "",
+                "/etc/passwd",
+                "/var/lib/filite/files",
+                "/var/lib/filite/files-old/file.txt",
+                "/var/lib/filite/files/../secret",
+                "/var/lib/filite/files/./file.txt/..",
+                "../secret",
+                "nested/../../secret",
+            ] {
+                assert_eq!(resolve_legacy_path(files_dir, l_path), None, "{}", l_path);
+            }
+        }
+
+        #[cfg(not(feature = "dev"))]
+        /// Empty static files root for a test
+        fn test_files_dir(name: &str) -> PathBuf {
+            let dir =
+                std::env::temp_dir().join(format!("filite-test-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        #[cfg(not(feature = "dev"))]
+        /// Inserts an entry shaped like those of versions without blobs, which stored the
+        /// absolute path of the upload prefixed with its base-36 time
+        fn insert_legacy(
+            conn: &SqliteConnection,
+            files_dir: &Path,
+            l_id: i32,
+            name: &str,
+        ) -> PathBuf {
+            let l_created = 1_600_000_000;
+            let path = files_dir.join(format!("{}.{}", radix_fmt::radix_36(l_created), name));
+            diesel::insert_into(table)
+                .values((
+                    id.eq(l_id),
+                    filepath.eq(path.to_str().unwrap()),
+                    created.eq(l_created),
+                ))
+                .execute(conn)
+                .unwrap();
+            path
+        }
+
+        #[cfg(not(feature = "dev"))]
+        #[test]
+        fn backfill_moves_legacy_files_into_blobs() {
+            let conn = crate::queries::test_database();
+            let files_dir = test_files_dir("backfill");
+            let storage = crate::storage::from_config(&StorageConfig::Local, &files_dir).unwrap();
+            let path = insert_legacy(&conn, &files_dir, 1, "notes.txt");
+            fs::write(&path, "hello\n").unwrap();
+
+            assert_eq!(backfill_in(&conn, &files_dir, &*storage).unwrap(), Some(1));
+            let file = files.find(1).first::<File>(&conn).unwrap();
+            let b_hash = blake3::hash(b"hello\n").to_hex().to_string();
+            assert_eq!(file.hash.as_deref(), Some(b_hash.as_str()));
+            assert_eq!(file.filepath, "notes.txt");
+            assert_eq!(file.original_name.as_deref(), Some("notes.txt"));
+            assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+            assert_eq!(file.size, Some(6));
+            assert!(is_referenced(&conn, &b_hash).unwrap());
+            assert_eq!(
+                fs::read(files_dir.join(blob_key(&b_hash))).unwrap(),
+                b"hello\n"
+            );
+            assert!(!path.exists());
+
+            // Recorded as complete, so it isn't run again
+            assert_eq!(backfill_in(&conn, &files_dir, &*storage).unwrap(), None);
+            fs::remove_dir_all(&files_dir).unwrap();
+        }
+
+        #[cfg(not(feature = "dev"))]
+        #[test]
+        fn backfill_runs_until_every_file_is_found() {
+            let conn = crate::queries::test_database();
+            let files_dir = test_files_dir("backfill-missing");
+            let storage = crate::storage::from_config(&StorageConfig::Local, &files_dir).unwrap();
+            let path = insert_legacy(&conn, &files_dir, 1, "photo.png");
+
+            assert_eq!(backfill_in(&conn, &files_dir, &*storage).unwrap(), Some(0));
+            assert_eq!(backfill_in(&conn, &files_dir, &*storage).unwrap(), Some(0));
+            fs::write(&path, b"\x89PNG\r\n\x1a\n").unwrap();
+            assert_eq!(backfill_in(&conn, &files_dir, &*storage).unwrap(), Some(1));
+            assert_eq!(backfill_in(&conn, &files_dir, &*storage).unwrap(), None);
+
+            let file = files.find(1).first::<File>(&conn).unwrap();
+            assert_eq!(file.original_name.as_deref(), Some("photo.png"));
+            assert_eq!(file.content_type.as_deref(), Some("image/png"));
+            fs::remove_dir_all(&files_dir).unwrap();
+        }
+
+        #[test]
+        fn keeps_legacy_names_without_an_upload_time() {
+            let file = |l_created| File {
+                id: 1,
+                filepath: String::new(),
+                created: l_created,
+                expires: None,
+                views_left: None,
+                owner: None,
+                slug: None,
+                hash: None,
+                deleted_at: None,
+                password_hash: None,
+                original_name: None,
+                content_type: None,
+                size: None,
+            };
+            let path = Path::new("/files/qgjaio.report.pdf");
+            let l_created = i32::from_str_radix("qgjaio", 36).unwrap();
+            assert_eq!(legacy_name(&file(l_created), path), "report.pdf");
+            assert_eq!(legacy_name(&file(l_created + 60), path), "report.pdf");
+            assert_eq!(legacy_name(&file(0), path), "qgjaio.report.pdf");
+            assert_eq!(
+                legacy_name(&file(0), Path::new("/files/readme.md")),
+                "readme.md"
+            );
+            assert_eq!(
+                legacy_name(&file(0), Path::new("/files/.bashrc")),
+                ".bashrc"
+            );
+        }
+    }
 }
 
 /// Queries affecting the `links` table
@@ -158,7 +1092,15 @@ pub mod links {
     use diesel::{prelude::*, result::QueryResult};
 
     find!(links, Link);
-    delete!(links, Link);
+    lookup!(links);
+    find_password!(links);
+    find_viewable!(links, Link);
+    view!(links, Link);
+    existing!(links);
+    delete!(links);
+    purge!(links, Link);
+    trash!(links, Link);
+    reap!(links);
 
     /// SELECT multiple link entries
     pub fn select(filters: SelectFilters) -> QueryResult<Vec<Link>> {
@@ -169,31 +1111,106 @@ pub mod links {
     }
 
     /// REPLACE a link entry
-    pub fn replace(r_id: i32, r_forward: &str) -> QueryResult<Link> {
+    pub fn replace(new_link: NewLink) -> QueryResult<Link> {
+        let r_id = new_link.id;
         let conn: &SqliteConnection = &POOL.get().unwrap();
-        let new_link = NewLink {
-            id: r_id,
-            forward: r_forward,
-        };
-        diesel::replace_into(table)
-            .values(&new_link)
-            .execute(conn)?;
+        conn.immediate_transaction(|| {
+            let e_entry = existing(conn, r_id)?;
+            let new_link = match &e_entry {
+                Some((e_owner, e_slug)) => NewLink {
+                    owner: e_owner.as_deref(),
+                    slug: new_link.slug.or(e_slug.as_deref()),
+                    ..new_link
+                },
+                None => new_link,
+            };
+            diesel::replace_into(table).values(&new_link).execute(conn)
+        })?;
         find(r_id)
     }
+
+    #[cfg(all(test, not(feature = "dev")))]
+    mod tests {
+        use super::*;
+
+        /// Inserts a link to example.com with the given views left
+        fn insert_link(conn: &SqliteConnection, l_id: i32, l_views_left: Option<i32>) {
+            diesel::insert_into(table)
+                .values(&NewLink {
+                    id: l_id,
+                    forward: "https://example.com",
+                    expires: None,
+                    views_left: l_views_left,
+                    owner: None,
+                    slug: None,
+                    password_hash: None,
+                })
+                .execute(conn)
+                .unwrap();
+        }
+
+        #[test]
+        fn counts_views() {
+            let conn = crate::queries::test_database();
+            insert_link(&conn, 1, Some(3));
+            assert_eq!(view_in(&conn, 1).unwrap().views_left, Some(2));
+            assert_eq!(view_in(&conn, 1).unwrap().views_left, Some(1));
+            insert_link(&conn, 2, None);
+            assert_eq!(view_in(&conn, 2).unwrap().views_left, None);
+        }
+
+        #[test]
+        fn deletes_links_on_their_last_view() {
+            let conn = crate::queries::test_database();
+            insert_link(&conn, 1, Some(1));
+            assert_eq!(view_in(&conn, 1).unwrap().views_left, Some(0));
+            assert_eq!(
+                view_in(&conn, 1).err(),
+                Some(diesel::result::Error::NotFound)
+            );
+        }
+
+        #[test]
+        fn rejects_exhausted_links() {
+            let conn = crate::queries::test_database();
+            insert_link(&conn, 1, Some(0));
+            assert_eq!(
+                view_in(&conn, 1).err(),
+                Some(diesel::result::Error::NotFound)
+            );
+            let left = links
+                .find(1)
+                .select(views_left)
+                .first::<Option<i32>>(&conn)
+                .unwrap();
+            assert_eq!(left, Some(0));
+        }
+    }
 }
 
 /// Queries affecting the `texts` table
 pub mod texts {
     use crate::{
         globals::POOL,
-        models::texts::*,
+        models::{text_revisions::*, texts::*},
         queries::SelectFilters,
-        schema::texts::{dsl::*, table},
+        schema::{
+            text_revisions,
+            texts::{dsl::*, table},
+        },
     };
     use diesel::{prelude::*, result::QueryResult};
 
     find!(texts, Text);
-    delete!(texts, Text);
+    lookup!(texts);
+    find_password!(texts);
+    find_viewable!(texts, Text);
+    view!(texts, Text);
+    existing!(texts);
+    delete!(texts);
+    purge!(texts, Text);
+    trash!(texts, Text);
+    reap!(texts);
 
     /// SELECT multiple text entries
     pub fn select(filters: SelectFilters) -> QueryResult<Vec<Text>> {
@@ -203,17 +1220,238 @@ pub mod texts {
         query.load::<Text>(conn)
     }
 
-    /// REPLACE a text entry
-    pub fn replace(r_id: i32, r_contents: &str, r_highlight: bool) -> QueryResult<Text> {
+    /// REPLACE a text entry, keeping the previous version as a revision
+    ///
+    /// The new version is credited to its author, who isn't the owner when an admin edits it.
+    pub fn replace(new_text: NewText) -> QueryResult<Text> {
+        let r_id = new_text.id;
         let conn: &SqliteConnection = &POOL.get().unwrap();
-        let new_text = NewText {
-            id: r_id,
-            contents: r_contents,
-            highlight: r_highlight,
-        };
-        diesel::replace_into(table)
-            .values(&new_text)
-            .execute(conn)?;
+        conn.immediate_transaction(|| {
+            let e_entry = existing(conn, r_id)?;
+            let new_text = match &e_entry {
+                Some((e_owner, e_slug)) => NewText {
+                    owner: e_owner.as_deref(),
+                    slug: new_text.slug.or(e_slug.as_deref()),
+                    ..new_text
+                },
+                None => new_text,
+            };
+            if let Some(old) = texts.find(new_text.id).first::<Text>(conn).optional()? {
+                diesel::insert_into(text_revisions::table)
+                    .values(&NewTextRevision {
+                        text_id: old.id,
+                        revision: latest_revision(conn, old.id)?,
+                        contents: &old.contents,
+                        format: &old.format,
+                        language: old.language.as_deref(),
+                        created: old.created,
+                        author: old.author.as_deref(),
+                    })
+                    .execute(conn)?;
+            }
+            diesel::replace_into(table).values(&new_text).execute(conn)
+        })?;
         find(r_id)
     }
+
+    /// Returns the revision number of the current version of a text
+    fn latest_revision(conn: &SqliteConnection, r_id: i32) -> QueryResult<i32> {
+        let previous = text_revisions::table
+            .select(diesel::dsl::max(text_revisions::revision))
+            .filter(text_revisions::text_id.eq(r_id))
+            .first::<Option<i32>>(conn)?;
+        Ok(previous.unwrap_or(0) + 1)
+    }
+
+    /// Returns the revision number of the current version of a text entry
+    pub fn current_revision(r_id: i32) -> QueryResult<i32> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        latest_revision(conn, r_id)
+    }
+
+    /// SELECT the previous versions of a text entry, oldest first
+    pub fn revisions(r_id: i32) -> QueryResult<Vec<TextRevision>> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        text_revisions::table
+            .filter(text_revisions::text_id.eq(r_id))
+            .order(text_revisions::revision.asc())
+            .load::<TextRevision>(conn)
+    }
+
+    /// SELECT a previous version of a text entry given its revision number
+    pub fn revision(r_id: i32, r_revision: i32) -> QueryResult<TextRevision> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        text_revisions::table
+            .filter(text_revisions::text_id.eq(r_id))
+            .filter(text_revisions::revision.eq(r_revision))
+            .first::<TextRevision>(conn)
+    }
+}
+
+/// Queries affecting the `tokens` table
+pub mod tokens {
+    use crate::{
+        globals::POOL,
+        models::tokens::*,
+        schema::tokens::{dsl::*, table},
+    };
+    use diesel::{prelude::*, result::QueryResult};
+
+    /// SELECT a single token given its id
+    pub fn find(f_id: i32) -> QueryResult<Token> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        tokens.find(f_id).first::<Token>(conn)
+    }
+
+    /// SELECT a single token given its hash
+    pub fn find_by_hash(f_hash: &str) -> QueryResult<Token> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        tokens.filter(token_hash.eq(f_hash)).first::<Token>(conn)
+    }
+
+    /// SELECT all tokens, or only those owned by a given user
+    pub fn select(s_owner: Option<&str>) -> QueryResult<Vec<Token>> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        let mut query = tokens.into_boxed();
+        if let Some(o) = s_owner {
+            query = query.filter(owner.eq(o));
+        }
+        query.order(created.desc()).load::<Token>(conn)
+    }
+
+    /// INSERT a new token
+    pub fn insert(new_token: NewToken) -> QueryResult<Token> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        diesel::insert_into(table)
+            .values(&new_token)
+            .execute(conn)?;
+        find_by_hash(new_token.token_hash)
+    }
+
+    /// DELETE a token given its id
+    pub fn delete(d_id: i32) -> QueryResult<()> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        diesel::delete(&tokens.find(d_id).first::<Token>(conn)?).execute(conn)?;
+        Ok(())
+    }
+
+    /// DELETE all tokens owned by a user
+    pub fn delete_owned(d_owner: &str) -> QueryResult<usize> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        diesel::delete(tokens.filter(owner.eq(d_owner))).execute(conn)
+    }
+}
+
+/// Queries affecting the `uploads` table
+pub mod uploads {
+    use crate::{
+        globals::{CONFIG, POOL},
+        models::uploads::*,
+        schema::uploads::{dsl::*, table},
+    };
+    use diesel::{
+        prelude::*,
+        result::{DatabaseErrorKind, Error, QueryResult},
+    };
+    use std::{fs, io::ErrorKind, path::PathBuf};
+
+    /// Number of seconds after which unfinished uploads are deleted
+    pub const LIFETIME: i32 = 24 * 60 * 60;
+
+    /// Returns the path of the file an upload is staged in
+    pub fn staged_path(s_id: &str) -> PathBuf {
+        CONFIG.files_dir.join(".uploads").join(s_id)
+    }
+
+    /// SELECT a single upload given its id
+    pub fn find(f_id: &str) -> QueryResult<Upload> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        uploads.find(f_id).first::<Upload>(conn)
+    }
+
+    /// INSERT a new upload
+    pub fn insert(new_upload: NewUpload) -> QueryResult<Upload> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        diesel::insert_into(table)
+            .values(&new_upload)
+            .execute(conn)?;
+        find(new_upload.id)
+    }
+
+    /// DELETE an upload along with its staged file
+    pub fn delete(d_id: &str) -> QueryResult<()> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        diesel::delete(&uploads.find(d_id).first::<Upload>(conn)?).execute(conn)?;
+        match fs::remove_file(staged_path(d_id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::DatabaseError(
+                DatabaseErrorKind::UnableToSendCommand,
+                Box::new(format!("{}", e)),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// DELETE all uploads left unfinished for longer than their lifetime
+    pub fn reap(now: i32) -> QueryResult<usize> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        let expired = uploads
+            .select(id)
+            .filter(created.le(now - LIFETIME))
+            .load::<String>(conn)?;
+        for e_id in &expired {
+            delete(e_id)?;
+        }
+        Ok(expired.len())
+    }
+}
+
+/// Queries affecting the `users` table
+pub mod users {
+    use crate::{
+        globals::POOL,
+        models::users::*,
+        schema::users::{dsl::*, table},
+    };
+    use diesel::{prelude::*, result::QueryResult};
+
+    /// SELECT a single user given its username
+    pub fn find(f_username: &str) -> QueryResult<User> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        users.filter(username.eq(f_username)).first::<User>(conn)
+    }
+
+    /// SELECT all users
+    pub fn select() -> QueryResult<Vec<User>> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        users.order(username.asc()).load::<User>(conn)
+    }
+
+    /// COUNT the existing users
+    pub fn count() -> QueryResult<i64> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        users.count().get_result(conn)
+    }
+
+    /// INSERT a new user or UPDATE an existing one with the same username
+    pub fn upsert(new_user: NewUser) -> QueryResult<User> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        let updated = diesel::update(users.filter(username.eq(new_user.username)))
+            .set((
+                password_hash.eq(new_user.password_hash),
+                admin.eq(new_user.admin),
+            ))
+            .execute(conn)?;
+        if updated == 0 {
+            diesel::insert_into(table).values(&new_user).execute(conn)?;
+        }
+        find(new_user.username)
+    }
+
+    /// DELETE a user given its username
+    pub fn delete(d_username: &str) -> QueryResult<()> {
+        let conn: &SqliteConnection = &POOL.get().unwrap();
+        diesel::delete(&users.filter(username.eq(d_username)).first::<User>(conn)?)
+            .execute(conn)?;
+        Ok(())
+    }
 }
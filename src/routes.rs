@@ -1,53 +1,176 @@
 //! Actix route handlers
 
 use crate::{
-    globals::{CONFIG, EMPTY_HASH, PASSWORD_HASH},
-    setup,
+    error::ApiError,
+    globals::{CONFIG, DUMMY_PASSWORD_HASH, LEGACY_PASSWORD, SECRET},
+    models::{files::File, links::Link, texts::Text, tokens::Scope},
+    queries, setup,
 };
+use actix_files::HttpRange;
 use actix_identity::Identity;
-use actix_web::{error::BlockingError, web, Error, HttpRequest, HttpResponse, Responder};
+use actix_web::{
+    cookie::{Cookie, CookieJar, Key, SameSite},
+    dev::{HttpResponseBuilder, RequestHead},
+    error::BlockingError,
+    http::{
+        header::{self, EntityTag, HeaderValue, HttpDate},
+        StatusCode,
+    },
+    web::{self, Bytes},
+    Error, HttpMessage, HttpRequest, HttpResponse, Responder,
+};
 use chrono::{DateTime, NaiveDateTime, Utc};
 use diesel;
 use serde::Serialize;
-use std::convert::Infallible;
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 #[cfg(feature = "dev")]
 use crate::get_env;
 #[cfg(feature = "dev")]
 use std::{fs, path::PathBuf};
 
-/// Parses an ID
-fn parse_id(id: &str) -> Result<i32, HttpResponse> {
-    // Remove any file extension from id
-    let id = id.split('.').next().unwrap_or_default();
+/// Returns the part of a path identifying an entry, either a slug or a base-36 id
+fn path_key(path: &str) -> &str {
+    // Remove any file extension from the path
+    path.split('.').next().unwrap_or_default()
+}
 
-    match i32::from_str_radix(id, 36) {
-        Ok(id) => Ok(id),
-        Err(_) => Err(HttpResponse::BadRequest().body("Invalid ID")),
+/// Checks that a slug is URL-safe and isn't a reserved word
+fn validate_slug(slug: &str) -> Result<(), ApiError> {
+    if slug.is_empty()
+        || slug.len() > 64
+        || slug.starts_with('-')
+        || !slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(ApiError::BadRequest("Invalid slug".to_owned()));
+    }
+    if CONFIG
+        .reserved_slugs
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(slug))
+    {
+        return Err(ApiError::BadRequest("Reserved slug".to_owned()));
     }
+    Ok(())
 }
 
-/// Authenticates a user
-async fn auth(identity: Identity, request: HttpRequest) -> Result<(), HttpResponse> {
-    if identity.identity().is_some() {
-        return Ok(());
+/// An authenticated user
+pub struct AuthUser {
+    /// Name the user is logged in as
+    pub username: String,
+    /// Whether the user can see and modify all entries
+    pub admin: bool,
+    /// Whether the user authenticated with an API token
+    pub token: bool,
+}
+
+impl AuthUser {
+    /// Returns the owner entries should be filtered by, or `None` if all entries are visible
+    pub fn owner_filter(&self) -> Option<String> {
+        if self.admin {
+            None
+        } else {
+            Some(self.username.clone())
+        }
+    }
+
+    /// Whether the user can modify an entry with the given owner
+    pub fn owns(&self, owner: Option<&str>) -> bool {
+        self.admin || owner == Some(self.username.as_str())
+    }
+}
+
+/// Authenticates a user using an API token
+async fn token_auth(token: &str, scope: Option<Scope>) -> Result<AuthUser, ApiError> {
+    let token_hash = setup::hash_token(token);
+    let token = match web::block(move || queries::tokens::find_by_hash(&token_hash)).await {
+        Ok(t) => t,
+        Err(BlockingError::Error(diesel::result::Error::NotFound)) => {
+            return Err(ApiError::Unauthorized("Bearer", "Invalid token".to_owned()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if queries::is_expired(token.expires) {
+        return Err(ApiError::Unauthorized("Bearer", "Expired token".to_owned()));
+    }
+    if let Some(scope) = scope {
+        if !token.allows(scope) {
+            return Err(ApiError::Forbidden(format!("Missing scope `{}`", scope)));
+        }
+    }
+
+    match web::block(move || queries::users::find(&token.owner)).await {
+        Ok(user) => Ok(AuthUser {
+            username: user.username,
+            admin: user.admin,
+            token: true,
+        }),
+        Err(BlockingError::Error(diesel::result::Error::NotFound)) => {
+            Err(ApiError::Unauthorized("Bearer", "Invalid token".to_owned()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Error for malformed "Authorization" headers
+fn invalid_authorization() -> ApiError {
+    ApiError::BadRequest("Invalid Authorization header".to_owned())
+}
+
+/// Authenticates a user, requiring the given scope if an API token is used
+async fn auth(
+    identity: Identity,
+    request: HttpRequest,
+    scope: Option<Scope>,
+) -> Result<AuthUser, ApiError> {
+    if let Some(Ok(header)) = request.headers().get("Authorization").map(|h| h.to_str()) {
+        if let Some(token) = header.strip_prefix("Bearer ") {
+            return token_auth(token.trim(), scope).await;
+        }
+    }
+
+    if let Some(username) = identity.identity() {
+        match web::block(move || queries::users::find(&username)).await {
+            Ok(user) => {
+                return Ok(AuthUser {
+                    username: user.username,
+                    admin: user.admin,
+                    token: false,
+                })
+            }
+            Err(BlockingError::Error(diesel::result::Error::NotFound)) => identity.forget(),
+            Err(e) => return Err(e.into()),
+        }
     }
 
-    if *PASSWORD_HASH == *EMPTY_HASH {
-        identity.remember("guest".into());
-        return Ok(());
+    // A single password from an older version keeps the instance locked until a user exists
+    let user_count = web::block(queries::users::count).await?;
+    if user_count == 0 {
+        if *LEGACY_PASSWORD {
+            return Err(ApiError::Unauthorized(
+                "Basic",
+                "Locked until an admin user is created with `filite passwd <username> --admin`"
+                    .to_owned(),
+            ));
+        }
+        return Ok(AuthUser {
+            username: "guest".to_owned(),
+            admin: true,
+            token: false,
+        });
     }
 
     let header = match request.headers().get("Authorization") {
         Some(h) => match h.to_str() {
             Ok(h) => h,
-            Err(_) => return Err(HttpResponse::BadRequest().body("Invalid Authorization header")),
+            Err(_) => return Err(invalid_authorization()),
         },
-        None => {
-            return Err(HttpResponse::Unauthorized()
-                .header("WWW-Authenticate", "Basic realm=\"filite\"")
-                .body("Unauthorized"))
-        }
+        None => return Err(ApiError::Unauthorized("Basic", "Unauthorized".to_owned())),
     };
     let connection_string = header.replace("Basic ", "");
     let (user, password) = match base64::decode(&connection_string) {
@@ -58,55 +181,80 @@ async fn auth(identity: Identity, request: HttpRequest) -> Result<(), HttpRespon
                 .collect::<Vec<Vec<u8>>>();
             match credentials.len() {
                 2 => (credentials[0].clone(), credentials[1].clone()),
-                _ => return Err(HttpResponse::BadRequest().body("Invalid Authorization header")),
+                _ => return Err(invalid_authorization()),
             }
         }
-        Err(_) => return Err(HttpResponse::BadRequest().body("Invalid Authorization header")),
+        Err(_) => return Err(invalid_authorization()),
     };
+    let username = String::from_utf8(user).map_err(|_| invalid_authorization())?;
 
-    let infallible_hash = move || -> Result<Vec<u8>, Infallible> { Ok(setup::hash(&password)) };
-    if web::block(infallible_hash).await.unwrap() == *PASSWORD_HASH {
-        match String::from_utf8(user.to_vec()) {
-            Ok(u) => {
-                identity.remember(u);
-                Ok(())
+    let find_username = username.clone();
+    let found = match web::block(move || queries::users::find(&find_username)).await {
+        Ok(user) => Some(user),
+        Err(BlockingError::Error(diesel::result::Error::NotFound)) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    // Hashing runs on the thread pool since Argon2 is purposefully slow, and unknown users
+    // are still checked against a hash so they can't be told apart by how long it takes
+    let check = move || {
+        Ok::<_, ()>(match found {
+            Some(user) if setup::verify_password(&password, &user.password_hash) => {
+                Some(user.admin)
             }
-            Err(_) => Err(HttpResponse::BadRequest().body("Invalid Authorization header")),
-        }
-    } else {
-        Err(HttpResponse::Unauthorized()
-            .header("WWW-Authenticate", "Basic realm=\"filite\"")
-            .body("Unauthorized"))
-    }
+            Some(_) => None,
+            None => {
+                setup::verify_password(&password, &DUMMY_PASSWORD_HASH);
+                None
+            }
+        })
+    };
+    let admin = match web::block(check).await {
+        Ok(Some(admin)) => admin,
+        _ => return Err(ApiError::Unauthorized("Basic", "Unauthorized".to_owned())),
+    };
+
+    identity.remember(username.clone());
+    Ok(AuthUser {
+        username,
+        admin,
+        token: false,
+    })
 }
 
 /// Match result from REPLACE queries
-fn match_replace_result<T: Serialize>(
-    result: Result<T, BlockingError<diesel::result::Error>>,
+fn match_replace_result<T: Serialize, E: fmt::Debug>(
+    result: Result<T, BlockingError<E>>,
     id: i32,
+    slug: Option<String>,
 ) -> Result<HttpResponse, Error> {
     match result {
-        Ok(_) => Ok(HttpResponse::Created().body(format!("{}", radix_fmt::radix_36(id)))),
-        Err(_) => Err(HttpResponse::InternalServerError()
-            .body("Internal server error")
-            .into()),
+        Ok(_) => Ok(HttpResponse::Created()
+            .body(slug.unwrap_or_else(|| format!("{}", radix_fmt::radix_36(id))))),
+        // Failing to replace an entry which was just found is never the client's fault
+        Err(e) => Err(ApiError::internal(e).into()),
     }
 }
 
 /// Handles error from single GET queries using find
 fn match_find_error<T>(error: BlockingError<diesel::result::Error>) -> Result<T, Error> {
-    match error {
-        BlockingError::Error(e) => match e {
-            diesel::result::Error::NotFound => {
-                Err(HttpResponse::NotFound().body("Not found").into())
-            }
-            _ => Err(HttpResponse::InternalServerError()
-                .body("Internal server error")
-                .into()),
-        },
-        BlockingError::Canceled => Err(HttpResponse::InternalServerError()
-            .body("Internal server error")
-            .into()),
+    Err(ApiError::from(error).into())
+}
+
+/// Checks that an entry hasn't expired yet
+fn check_expires(expires: Option<i32>) -> Result<(), ApiError> {
+    if queries::is_expired(expires) {
+        Err(ApiError::Gone)
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that a view limit is valid
+fn check_views(views: Option<i32>) -> Result<(), ApiError> {
+    match views {
+        Some(v) if v < 1 => Err(ApiError::BadRequest("Invalid view limit".to_owned())),
+        _ => Ok(()),
     }
 }
 
@@ -117,6 +265,103 @@ fn timestamp_to_last_modified(timestamp: i32) -> String {
     datetime.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
 }
 
+/// Converts an HTTP date to a UNIX timestamp
+fn http_date_to_timestamp(date: HttpDate) -> Option<i64> {
+    let time: SystemTime = date.into();
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs() as i64)
+}
+
+/// Returns the strong entity tag of some contents, derived from their BLAKE3 digest
+fn content_etag(contents: &[u8]) -> EntityTag {
+    EntityTag::strong(blake3::hash(contents).to_hex().to_string())
+}
+
+/// Sets the "ETag" and "Last-Modified" headers of a response
+fn set_validators(response: &mut HttpResponse, etag: &EntityTag, created: i32) {
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&etag.to_string()) {
+        headers.insert(header::ETAG, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&timestamp_to_last_modified(created)) {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+}
+
+/// Checks the conditional headers of a request, responding with "304 Not Modified" if they match
+fn check_modified(
+    request: &HttpRequest,
+    etag: &EntityTag,
+    created: i32,
+) -> Result<(), HttpResponse> {
+    let not_modified = match request.get_header::<header::IfNoneMatch>() {
+        Some(header::IfNoneMatch::Any) => true,
+        Some(header::IfNoneMatch::Items(items)) => items.iter().any(|i| i.weak_eq(etag)),
+        // "If-Modified-Since" is only considered when "If-None-Match" is absent
+        None => match request.get_header::<header::IfModifiedSince>() {
+            Some(header::IfModifiedSince(since)) => {
+                http_date_to_timestamp(since).is_some_and(|s| s >= i64::from(created))
+            }
+            None => false,
+        },
+    };
+
+    if not_modified {
+        let mut response = HttpResponse::NotModified().finish();
+        set_validators(&mut response, etag, created);
+        Err(response)
+    } else {
+        Ok(())
+    }
+}
+
+/// Serves a body, or the part of it requested by a "Range" header
+fn ranged_response(
+    request: &HttpRequest,
+    mut response: HttpResponseBuilder,
+    body: Bytes,
+    etag: &EntityTag,
+    created: i32,
+) -> HttpResponse {
+    response.header(header::ACCEPT_RANGES, "bytes");
+    let length = body.len() as u64;
+
+    // A range is only served if the representation it was requested for is still current
+    let range = match request.get_header::<header::IfRange>() {
+        Some(header::IfRange::EntityTag(tag)) if !tag.strong_eq(etag) => None,
+        Some(header::IfRange::Date(date))
+            if http_date_to_timestamp(date) != Some(i64::from(created)) =>
+        {
+            None
+        }
+        _ => request.headers().get(header::RANGE),
+    };
+    let mut served = match range.map(|r| r.to_str().map(|r| HttpRange::parse(r, length))) {
+        Some(Ok(Ok(ranges))) => match ranges.first() {
+            Some(r) => {
+                let (start, end) = (r.start, r.start + r.length);
+                response
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(
+                        header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, end - 1, length),
+                    )
+                    .body(body.slice(start as usize..end as usize))
+            }
+            None => response.body(body),
+        },
+        Some(Ok(Err(_))) => response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", length))
+            .finish(),
+        Some(Err(_)) => response.status(StatusCode::BAD_REQUEST).finish(),
+        None => response.body(body),
+    };
+    set_validators(&mut served, etag, created);
+    served
+}
+
 /// Escapes text to be inserted in a HTML element
 fn escape_html(text: &str) -> String {
     text.replace("&", "&amp;")
@@ -124,22 +369,197 @@ fn escape_html(text: &str) -> String {
         .replace(">", "&gt;")
 }
 
+/// Header a request can give the password of a protected entry in
+const PASSWORD_HEADER: &str = "X-Entry-Password";
+
+/// How long a protected entry stays unlocked once its password was given, in seconds
+const UNLOCK_DURATION: i64 = 60 * 60;
+
+/// Query string giving the password of a protected entry
+#[derive(Deserialize)]
+struct PasswordQuery {
+    password: Option<String>,
+}
+
+/// Form body sent by the unlock page of a protected entry
+#[derive(Deserialize)]
+pub struct UnlockForm {
+    pub password: String,
+}
+
+/// Access a request has to an entry
+enum Unlock {
+    /// The entry isn't protected
+    Public,
+    /// The entry is protected and was unlocked, with a cookie to set if a password was just given
+    Unlocked(Option<Cookie<'static>>),
+}
+
+/// Returns the password a request gives for an entry, from its header or query string
+fn request_password(request: &HttpRequest) -> Option<String> {
+    if let Some(password) = request.headers().get(PASSWORD_HEADER) {
+        return password.to_str().ok().map(str::to_owned);
+    }
+    web::Query::<PasswordQuery>::from_query(request.query_string())
+        .ok()
+        .and_then(|q| q.into_inner().password)
+}
+
+/// Returns the name of the cookie remembering that an entry was unlocked
+fn unlock_cookie_name(kind: &str, id: i32) -> String {
+    format!("filite-unlock-{}-{}", kind, radix_fmt::radix_36(id))
+}
+
+/// Returns the value of an unlock cookie
+///
+/// Cookie names aren't signed, so the value ties the cookie to its entry. It also
+/// contains a digest of the password hash so changing the password locks the entry again.
+fn unlock_cookie_value(kind: &str, id: i32, password_hash: &str, expires: i64) -> String {
+    let digest = blake3::hash(password_hash.as_bytes()).to_hex();
+    format!(
+        "{}.{}.{}.{}",
+        kind,
+        radix_fmt::radix_36(id),
+        expires,
+        &digest[..16]
+    )
+}
+
+/// Creates a signed cookie unlocking an entry
+///
+/// The cookie is sent with every path under `/{kind}/` and tied to the entry by its name and
+/// value, so the entry stays unlocked whether it's reached by id or slug, with an extension or
+/// through one of its subpaths.
+fn unlock_cookie(kind: &str, id: i32, password_hash: &str) -> Cookie<'static> {
+    let name = unlock_cookie_name(kind, id);
+    let expires = Utc::now().timestamp() + UNLOCK_DURATION;
+    let mut jar = CookieJar::new();
+    jar.signed(&Key::from_master(&SECRET)).add(
+        Cookie::build(
+            name.clone(),
+            unlock_cookie_value(kind, id, password_hash, expires),
+        )
+        .path(format!("/{}/", kind))
+        .max_age(UNLOCK_DURATION)
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .finish(),
+    );
+    jar.get(&name).cloned().expect("Can't sign unlock cookie")
+}
+
+/// Whether a request has a valid cookie unlocking an entry
+fn has_unlock_cookie(request: &HttpRequest, kind: &str, id: i32, password_hash: &str) -> bool {
+    let name = unlock_cookie_name(kind, id);
+    let cookie = match request.cookie(&name) {
+        Some(c) => c,
+        None => return false,
+    };
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie);
+    let value = match jar.signed(&Key::from_master(&SECRET)).get(&name) {
+        Some(c) => c.value().to_owned(),
+        None => return false,
+    };
+
+    let expires = match value.split('.').nth(2).and_then(|e| e.parse::<i64>().ok()) {
+        Some(e) => e,
+        None => return false,
+    };
+    expires > Utc::now().timestamp()
+        && value == unlock_cookie_value(kind, id, password_hash, expires)
+}
+
+/// Responds with the page asking for the password of a protected entry
+fn locked_response(key: &str, wrong_password: bool) -> HttpResponse {
+    let error = if wrong_password {
+        r#"<p class="error">Wrong password</p>"#
+    } else {
+        ""
+    };
+    HttpResponse::Unauthorized()
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Cache-Control", "no-store")
+        .body(
+            UNLOCK_CONTENTS
+                .replace("{{ title }}", &escape_html(path_key(key)))
+                .replace("{{ error }}", error),
+        )
+}
+
+/// Checks that a request may view an entry, either because it isn't protected, because it
+/// was unlocked before or because the request gives its password
+///
+/// Responds with "401 Unauthorized" and an unlock page otherwise.
+async fn check_password(
+    request: &HttpRequest,
+    kind: &str,
+    key: &str,
+    id: i32,
+    password_hash: Option<String>,
+    password: Option<String>,
+) -> Result<Unlock, Error> {
+    let password_hash = match password_hash {
+        Some(h) => h,
+        None => return Ok(Unlock::Public),
+    };
+    if has_unlock_cookie(request, kind, id, &password_hash) {
+        return Ok(Unlock::Unlocked(None));
+    }
+
+    let password = match password {
+        Some(p) => p,
+        None => return Err(locked_response(key, false).into()),
+    };
+    let hash = password_hash.clone();
+    match web::block(move || Ok::<_, ()>(setup::verify_password(password.as_bytes(), &hash))).await
+    {
+        Ok(true) => Ok(Unlock::Unlocked(Some(unlock_cookie(
+            kind,
+            id,
+            &password_hash,
+        )))),
+        _ => Err(locked_response(key, true).into()),
+    }
+}
+
+/// Keeps the response to an unlocked entry out of shared caches and sets its unlock cookie
+fn set_unlocked(response: &mut HttpResponse, unlock: Unlock) {
+    if let Unlock::Unlocked(cookie) = unlock {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("private"));
+        if let Some(cookie) = cookie {
+            if let Err(e) = response.add_cookie(&cookie) {
+                eprintln!("Can't set unlock cookie: {}", e);
+            }
+        }
+    }
+}
+
+/// Hashes the password given to a new entry, if it isn't empty
+fn hash_entry_password(password: Option<&str>) -> Option<String> {
+    password
+        .filter(|p| !p.is_empty())
+        .map(|p| setup::hash_password(p.as_bytes()))
+}
+
 /// GET multiple entries
 macro_rules! select {
-    ($m:ident) => {
+    ($m:ident, $s:expr) => {
         pub async fn select(
             request: HttpRequest,
             query: actix_web::web::Query<SelectQuery>,
             identity: actix_identity::Identity,
         ) -> Result<actix_web::HttpResponse, actix_web::Error> {
-            crate::routes::auth(identity, request).await?;
+            let user = crate::routes::auth(identity, request, Some($s)).await?;
 
-            let filters = crate::queries::SelectFilters::from(query.into_inner());
+            let mut filters = crate::queries::SelectFilters::from(query.into_inner());
+            filters.owner = user.owner_filter();
             match actix_web::web::block(move || crate::queries::$m::select(filters)).await {
                 Ok(x) => Ok(actix_web::HttpResponse::Ok().json(x)),
-                Err(_) => Err(actix_web::HttpResponse::InternalServerError()
-                    .body("Internal server error")
-                    .into()),
+                Err(e) => Err(crate::error::ApiError::internal(e).into()),
             }
         }
     };
@@ -153,9 +573,15 @@ macro_rules! delete {
             path: actix_web::web::Path<String>,
             identity: actix_identity::Identity,
         ) -> Result<actix_web::HttpResponse, actix_web::Error> {
-            crate::routes::auth(identity, request).await?;
+            let user = crate::routes::auth(
+                identity,
+                request,
+                Some(crate::models::tokens::Scope::Delete),
+            )
+            .await?;
 
-            let id = crate::routes::parse_id(&path)?;
+            let id = resolve_id(&path).await?;
+            check_owner(id, &user).await?;
             match actix_web::web::block(move || crate::queries::$m::delete(id)).await {
                 Ok(()) => Ok(actix_web::HttpResponse::Ok().body("Deleted")),
                 Err(e) => crate::routes::match_find_error(e),
@@ -164,34 +590,196 @@ macro_rules! delete {
     };
 }
 
-/// Verify if an entry exists
+/// Restore and DELETE entries in the trash
+macro_rules! trash {
+    ($m:ident) => {
+        /// POST an entry in the trash to restore it
+        pub async fn restore(
+            request: HttpRequest,
+            path: actix_web::web::Path<String>,
+            identity: actix_identity::Identity,
+        ) -> Result<actix_web::HttpResponse, actix_web::Error> {
+            let user = crate::routes::auth(
+                identity,
+                request,
+                Some(crate::models::tokens::Scope::Delete),
+            )
+            .await?;
+
+            let id = resolve_id(&path).await?;
+            check_owner(id, &user).await?;
+            match actix_web::web::block(move || crate::queries::$m::restore(id)).await {
+                Ok(()) => Ok(actix_web::HttpResponse::Ok().body("Restored")),
+                Err(e) => crate::routes::match_find_error(e),
+            }
+        }
+
+        /// DELETE an entry for good, whether it's in the trash or not
+        pub async fn purge(
+            request: HttpRequest,
+            path: actix_web::web::Path<String>,
+            identity: actix_identity::Identity,
+        ) -> Result<actix_web::HttpResponse, actix_web::Error> {
+            let user = crate::routes::auth(
+                identity,
+                request,
+                Some(crate::models::tokens::Scope::Delete),
+            )
+            .await?;
+
+            let id = resolve_id(&path).await?;
+            check_owner(id, &user).await?;
+            match actix_web::web::block(move || crate::queries::$m::purge(id)).await {
+                Ok(()) => Ok(actix_web::HttpResponse::Ok().body("Purged")),
+                Err(e) => crate::routes::match_find_error(e),
+            }
+        }
+    };
+}
+
+/// Protect entries with passwords
+macro_rules! protect {
+    ($m:ident, $k:expr) => {
+        /// Checks that a request may view an entry, responding with an unlock page if it can't
+        async fn check_password(
+            request: &HttpRequest,
+            key: &str,
+            id: i32,
+        ) -> Result<crate::routes::Unlock, actix_web::Error> {
+            let password_hash =
+                match actix_web::web::block(move || crate::queries::$m::find_password(id)).await {
+                    Ok(h) => h,
+                    Err(e) => return crate::routes::match_find_error(e),
+                };
+            let password = crate::routes::request_password(request);
+            crate::routes::check_password(request, $k, key, id, password_hash, password).await
+        }
+
+        /// POST the password of an entry from its unlock page, going back to it once unlocked
+        pub async fn unlock(
+            request: HttpRequest,
+            form: actix_web::web::Form<crate::routes::UnlockForm>,
+        ) -> Result<actix_web::HttpResponse, actix_web::Error> {
+            let key = request
+                .match_info()
+                .get("id")
+                .unwrap_or_default()
+                .to_owned();
+            let id = resolve_id(&key).await?;
+            let password_hash =
+                match actix_web::web::block(move || crate::queries::$m::find_password(id)).await {
+                    Ok(h) => h,
+                    Err(e) => return crate::routes::match_find_error(e),
+                };
+            let password = Some(form.into_inner().password);
+            let unlock =
+                crate::routes::check_password(&request, $k, &key, id, password_hash, password)
+                    .await?;
+
+            let mut response = actix_web::HttpResponse::SeeOther()
+                .header("Location", request.uri().to_string())
+                .finish();
+            crate::routes::set_unlocked(&mut response, unlock);
+            Ok(response)
+        }
+    };
+}
+
+/// Generate a random id that isn't used by an entry or a slug
 macro_rules! random_id {
     ($m:ident) => {
         use rand::distributions::Distribution;
 
         pub async fn random_id() -> Result<i32, actix_web::Error> {
             let mut rng = rand::thread_rng();
-            let distribution = rand::distributions::Uniform::from(0..i32::max_value());
+            let distribution = rand::distributions::Uniform::from(0..i32::MAX);
             loop {
                 let id = distribution.sample(&mut rng);
-                match actix_web::web::block(move || crate::queries::$m::find(id)).await {
-                    Ok(_) => continue,
-                    Err(e) => match e {
-                        actix_web::error::BlockingError::Error(e) => match e {
-                            diesel::result::Error::NotFound => return Ok(id),
-                            _ => {
-                                return Err(actix_web::HttpResponse::InternalServerError()
-                                    .body("Internal server error")
-                                    .into())
-                            }
-                        },
-                        actix_web::error::BlockingError::Canceled => {
-                            return Err(actix_web::HttpResponse::InternalServerError()
-                                .body("Internal server error")
-                                .into())
-                        }
-                    },
+                let key = format!("{}", radix_fmt::radix_36(id));
+                match actix_web::web::block(move || crate::queries::$m::lookup(&key)).await {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => return Ok(id),
+                    Err(e) => return Err(crate::error::ApiError::internal(e).into()),
+                }
+            }
+        }
+    };
+}
+
+/// Resolve the entries paths refer to, either by slug or by base-36 id
+macro_rules! resolve {
+    ($m:ident) => {
+        /// Returns the id of the existing entry a path refers to
+        pub async fn resolve_id(path: &str) -> Result<i32, actix_web::Error> {
+            let key = crate::routes::path_key(path).to_owned();
+            match actix_web::web::block(move || crate::queries::$m::lookup(&key)).await {
+                Ok(Some(id)) => Ok(id),
+                Ok(None) => Err(crate::error::ApiError::NotFound.into()),
+                Err(e) => crate::routes::match_find_error(e),
+            }
+        }
+
+        /// Returns the id and slug a PUT request targets, generating an id for new slugs
+        pub async fn resolve_put(path: &str) -> Result<(i32, Option<String>), actix_web::Error> {
+            let key = crate::routes::path_key(path).to_owned();
+            let lookup_key = key.clone();
+            let existing = match actix_web::web::block(move || {
+                crate::queries::$m::lookup(&lookup_key)
+            })
+            .await
+            {
+                Ok(e) => e,
+                Err(e) => return crate::routes::match_find_error(e),
+            };
+
+            match (existing, crate::queries::parse_id(&key)) {
+                (Some(id), Some(numeric)) if id == numeric => Ok((id, None)),
+                (Some(id), _) => Ok((id, Some(key))),
+                (None, Some(numeric)) => Ok((numeric, None)),
+                (None, None) => {
+                    crate::routes::validate_slug(&key)?;
+                    Ok((random_id().await?, Some(key)))
+                }
+            }
+        }
+
+        /// Checks that a slug is valid and doesn't refer to another entry
+        pub async fn check_slug(slug: String, id: i32) -> Result<(), actix_web::Error> {
+            crate::routes::validate_slug(&slug)?;
+            match actix_web::web::block(move || crate::queries::$m::lookup(&slug)).await {
+                Ok(Some(other)) if other != id => {
+                    Err(crate::error::ApiError::Conflict("Slug already in use".to_owned()).into())
+                }
+                Ok(_) => Ok(()),
+                Err(e) => crate::routes::match_find_error(e),
+            }
+        }
+    };
+}
+
+/// Verify that a user is allowed to modify an entry
+macro_rules! check_owner {
+    ($m:ident) => {
+        pub async fn check_owner(
+            id: i32,
+            user: &crate::routes::AuthUser,
+        ) -> Result<(), actix_web::Error> {
+            if user.admin {
+                return Ok(());
+            }
+
+            match actix_web::web::block(move || crate::queries::$m::find(id)).await {
+                Ok(entry) => {
+                    if user.owns(entry.owner.as_deref()) {
+                        Ok(())
+                    } else {
+                        Err(crate::error::ApiError::Forbidden("Forbidden".to_owned()).into())
+                    }
+                }
+                Err(actix_web::error::BlockingError::Error(diesel::result::Error::NotFound)) => {
+                    Ok(())
                 }
+                Err(e) => crate::routes::match_find_error(e),
             }
         }
     };
@@ -212,13 +800,13 @@ lazy_static! {
 static INDEX_CONTENTS: &str = include_str!("../resources/index.html");
 
 static HIGHLIGHT_CONTENTS: &str = include_str!("../resources/highlight.html");
-const HIGHLIGHT_LANGUAGE: &str = r#"<script src="https://cdnjs.cloudflare.com/ajax/libs/highlight.js/9.15.10/languages/{{ language }}.min.js"></script>"#;
+static MARKDOWN_CONTENTS: &str = include_str!("../resources/markdown.html");
+static DECRYPT_CONTENTS: &str = include_str!("../resources/decrypt.html");
+static UNLOCK_CONTENTS: &str = include_str!("../resources/unlock.html");
 
 /// Index page letting users upload via a UI
-pub async fn index(request: HttpRequest, identity: Identity) -> impl Responder {
-    if let Err(response) = auth(identity, request).await {
-        return response;
-    }
+pub async fn index(request: HttpRequest, identity: Identity) -> Result<HttpResponse, Error> {
+    auth(identity, request, None).await?;
 
     let contents = {
         #[cfg(feature = "dev")]
@@ -230,165 +818,784 @@ pub async fn index(request: HttpRequest, identity: Identity) -> impl Responder {
             INDEX_CONTENTS.to_owned()
         }
     };
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .header("Content-Type", "text/html")
-        .body(contents)
+        .body(contents))
+}
+
+/// Entries in the trash, grouped by type
+#[derive(Serialize)]
+pub struct Trash {
+    pub files: Vec<File>,
+    pub links: Vec<Link>,
+    pub texts: Vec<Text>,
+}
+
+/// GET the entries in the trash
+pub async fn trash(request: HttpRequest, identity: Identity) -> Result<HttpResponse, Error> {
+    let user = auth(identity, request, Some(Scope::Delete)).await?;
+    let owner = user.owner_filter();
+    let trash = web::block(move || -> diesel::result::QueryResult<Trash> {
+        let owner = owner.as_deref();
+        Ok(Trash {
+            files: queries::files::trashed(owner)?,
+            links: queries::links::trashed(owner)?,
+            texts: queries::texts::trashed(owner)?,
+        })
+    })
+    .await
+    .map_err(ApiError::from)?;
+    Ok(HttpResponse::Ok().json(trash))
 }
 
 /// GET the config info
-pub async fn get_config(request: HttpRequest, identity: Identity) -> impl Responder {
-    match auth(identity, request).await {
-        Ok(_) => HttpResponse::Ok().json(&*CONFIG),
-        Err(response) => response,
-    }
+pub async fn get_config(request: HttpRequest, identity: Identity) -> Result<HttpResponse, Error> {
+    auth(identity, request, None).await?;
+    Ok(HttpResponse::Ok().json(&*CONFIG))
 }
 
 /// Logout route
 pub async fn logout(identity: Identity) -> impl Responder {
     if identity.identity().is_some() {
         identity.forget();
-        HttpResponse::Ok().body("Logged out")
+        Ok(HttpResponse::Ok().body("Logged out"))
     } else {
-        HttpResponse::Unauthorized()
-            .header("WWW-Authenticate", "Basic realm=\"filite\"")
-            .body("Unauthorized")
+        Err(ApiError::Unauthorized("Basic", "Unauthorized".to_owned()))
     }
 }
 
+/// Port of the HTTPS listener plain HTTP requests are redirected to
+#[derive(Clone, Copy)]
+pub struct HttpsPort(pub u16);
+
+/// Redirects plain HTTP requests to HTTPS
+pub async fn redirect_https(
+    request: HttpRequest,
+    https_port: web::Data<HttpsPort>,
+) -> impl Responder {
+    let connection_info = request.connection_info();
+    let host = connection_info.host();
+    // Strip the port, taking IPv6 addresses into account
+    let host = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+    let port = match https_port.0 {
+        443 => String::new(),
+        p => format!(":{}", p),
+    };
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+
+    HttpResponse::MovedPermanently()
+        .header("Location", format!("https://{}{}{}", host, port, path))
+        .finish()
+}
+
+/// Whether a request was sent to the origin serving files, if there is one
+pub fn is_usercontent(head: &RequestHead) -> bool {
+    let usercontent = match CONFIG.usercontent_host() {
+        Some(h) => h,
+        None => return false,
+    };
+    head.headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| head.uri.authority().map(|a| a.as_str()))
+        .is_some_and(|h| h.eq_ignore_ascii_case(&usercontent))
+}
+
+/// Responds to requests which don't match any route
+pub async fn not_found() -> Result<HttpResponse, Error> {
+    Err(ApiError::NotFound.into())
+}
+
 pub async fn id_to_str(path: web::Path<String>) -> impl Responder {
     let id: i32 = match path.parse() {
         Ok(id) => id,
-        Err(_) => return Err(HttpResponse::BadRequest().body("Invalid ID")),
+        Err(_) => return Err(ApiError::BadRequest("Invalid ID".to_owned())),
     };
     Ok(HttpResponse::Ok().body(radix_fmt::radix_36(id).to_string()))
 }
 
 pub mod files {
+    use crate::models::tokens::Scope;
     use crate::routes::match_replace_result;
     use crate::{
-        globals::CONFIG,
-        queries::{self, SelectQuery},
-        routes::{auth, match_find_error, parse_id},
+        error::ApiError,
+        filename,
+        globals::{CONFIG, STORAGE},
+        models::{self, files::NewFile},
+        queries::{self, files::StoreError, SelectQuery},
+        routes::{
+            auth, check_expires, check_modified, check_views, hash_entry_password, is_usercontent,
+            set_unlocked, set_validators, AuthUser, Unlock,
+        },
+        sniff, thumbnail,
     };
     use actix_files::NamedFile;
     use actix_identity::Identity;
-    use actix_multipart::Multipart;
-    use actix_web::{web, Error, HttpRequest, HttpResponse};
+    use actix_multipart::{Field, Multipart};
+    use actix_web::{
+        error::BlockingError,
+        http::header::{
+            self, Charset, ContentDisposition, DispositionParam, DispositionType, EntityTag,
+            ExtendedValue, HeaderValue,
+        },
+        web::{self, Bytes},
+        Error, HttpRequest, HttpResponse,
+    };
     use chrono::Utc;
-    use futures::StreamExt;
+    use futures::{stream, StreamExt};
+    use image::ImageError;
+    use mime_guess::{mime, Mime};
     use std::{
         fs::{self, File},
-        io::Write,
-        path::PathBuf,
+        io::{self, Read, Write},
+        path::{Path, PathBuf},
+        time::UNIX_EPOCH,
     };
 
-    select!(files);
+    select!(files, Scope::FilesRead);
     delete!(files);
+    trash!(files);
     random_id!(files);
+    resolve!(files);
+    check_owner!(files);
+    protect!(files, "f");
 
-    /// GET a file entry and statically serve it
-    pub async fn get(path: web::Path<String>) -> Result<NamedFile, Error> {
-        let id = parse_id(&path)?;
-        match web::block(move || queries::files::find(id)).await {
-            Ok(file) => {
-                let mut path = CONFIG.files_dir.clone();
-                path.push(file.filepath);
-                match NamedFile::open(&path) {
-                    Ok(nf) => Ok(nf),
-                    Err(_) => Err(HttpResponse::NotFound().body("Not found").into()),
-                }
-            }
-            Err(e) => match_find_error(e),
+    /// Opened contents of a file entry
+    enum Contents {
+        /// File on the local filesystem, served statically
+        Local(Box<NamedFile>),
+        /// Reader streaming the whole contents, with how to serve them
+        Stream(Box<dyn Read + Send>, Mime, DispositionType),
+    }
+
+    /// Policy of file responses, which can't run scripts, load anything or be sniffed
+    const FILE_POLICY: &str =
+        "default-src 'none'; img-src 'self' data:; media-src 'self'; style-src 'unsafe-inline'; sandbox";
+    /// Maximum length of multipart fields other than the file, in bytes
+    const MAX_FIELD_LENGTH: usize = 4 * 1024;
+
+    /// Returns the MIME type of a file entry, guessing it from its name if it wasn't detected
+    fn file_type(file: &models::files::File) -> Mime {
+        file.content_type
+            .as_deref()
+            .and_then(|t| t.parse().ok())
+            .unwrap_or_else(|| mime_guess::from_path(&file.filepath).first_or_octet_stream())
+    }
+
+    /// Returns the MIME type and disposition a file entry is served with
+    ///
+    /// Types which browsers could run scripts from are served as plain text or downloaded, unless
+    /// files are served from their own origin where they can't reach anything else.
+    fn serving_type(file: &models::files::File, usercontent: bool) -> (Mime, DispositionType) {
+        let content_type = file_type(file);
+        let passive = match content_type.type_() {
+            mime::IMAGE => content_type.subtype() != mime::SVG,
+            mime::TEXT => content_type.subtype() == mime::PLAIN,
+            mime::VIDEO | mime::AUDIO => true,
+            _ => false,
+        };
+        let inline = matches!(content_type.type_(), mime::IMAGE | mime::TEXT);
+
+        if passive || (usercontent && inline) {
+            (content_type, DispositionType::Inline)
+        } else if inline {
+            (mime::TEXT_PLAIN_UTF_8, DispositionType::Inline)
+        } else {
+            (content_type, DispositionType::Attachment)
         }
     }
 
-    /// Common code for PUT and POST routes
-    async fn put_post(id: i32, mut body: Multipart) -> Result<HttpResponse, Error> {
-        let mut path = CONFIG.files_dir.clone();
-        let mut relative_path = PathBuf::new();
-        let dir_path = path.clone();
-        if web::block(move || fs::create_dir_all(dir_path))
-            .await
-            .is_err()
-        {
-            return Err(HttpResponse::InternalServerError()
-                .body("Internal server error")
-                .into());
+    /// Builds the `Content-Disposition` of a file entry, giving back the name it was uploaded with
+    fn content_disposition(
+        file: &models::files::File,
+        disposition: DispositionType,
+    ) -> ContentDisposition {
+        let name = Path::new(&file.filepath)
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let fallback = filename::ascii_fallback(&name);
+
+        // The original name may contain anything, so it's only sent percent-encoded
+        let original = file.original_name.clone().unwrap_or(name);
+        let mut parameters = vec![DispositionParam::Filename(fallback.clone())];
+        if original != fallback {
+            parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_owned()),
+                language_tag: None,
+                value: original.into_bytes(),
+            }));
+        }
+        ContentDisposition {
+            disposition,
+            parameters,
         }
+    }
 
-        let mut field = match body.next().await {
-            Some(f) => f?,
-            None => {
-                return Err(HttpResponse::BadRequest()
-                    .body("Empty multipart body")
-                    .into())
-            }
-        };
-        let content_disposition = match field.content_disposition() {
-            Some(cd) => cd,
-            None => {
-                return Err(HttpResponse::BadRequest()
-                    .body("Missing content disposition")
-                    .into())
-            }
+    /// Opens the contents of a file entry from wherever they are stored
+    ///
+    /// Entries with a view limit are always streamed whole, so each view sends all of the
+    /// contents instead of the part asked for by a range request.
+    fn open(file: &models::files::File, usercontent: bool) -> Result<Contents, ApiError> {
+        let (content_type, disposition) = serving_type(file, usercontent);
+        let open_local = |path: PathBuf| match File::open(&path) {
+            Ok(f) if file.views_left.is_some() => Ok(Contents::Stream(
+                Box::new(f),
+                content_type.clone(),
+                disposition.clone(),
+            )),
+            Ok(f) => match NamedFile::from_file(f, &file.filepath) {
+                Ok(nf) => Ok(Contents::Local(Box::new(
+                    nf.set_content_type(content_type.clone())
+                        .set_content_disposition(content_disposition(file, disposition.clone())),
+                ))),
+                Err(_) => Err(ApiError::NotFound),
+            },
+            Err(_) => Err(ApiError::NotFound),
         };
-        let filename = match content_disposition.get_filename() {
-            Some(n) => n,
-            None => return Err(HttpResponse::BadRequest().body("Missing filename").into()),
-        };
-        let filename = format!(
-            "{}.{}",
-            radix_fmt::radix_36(Utc::now().timestamp()),
-            filename
-        );
-        path.push(&filename);
-        relative_path.push(&filename);
-        let relative_path = match path.to_str() {
-            Some(rp) => rp.to_owned(),
-            None => {
-                return Err(HttpResponse::InternalServerError()
-                    .body("Internal server error")
-                    .into())
-            }
+
+        // Files uploaded before blobs were introduced are always stored locally
+        let key = match &file.hash {
+            Some(h) => queries::files::blob_key(h),
+            None => match queries::files::legacy_path(file) {
+                Some(path) => return open_local(path),
+                None => return Err(ApiError::NotFound),
+            },
         };
+        if let Some(path) = STORAGE.local_path(&key) {
+            return open_local(path);
+        }
 
-        let mut f = match web::block(move || File::create(&path)).await {
-            Ok(f) => f,
-            Err(_) => {
-                return Err(HttpResponse::InternalServerError()
-                    .body("Internal server error")
-                    .into())
+        match STORAGE.get(&key) {
+            Ok(reader) => Ok(Contents::Stream(reader, content_type, disposition)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(ApiError::NotFound),
+            Err(e) => Err(ApiError::internal(e)),
+        }
+    }
+
+    /// Streams contents read from a storage backend
+    fn stream_response(
+        reader: Box<dyn Read + Send>,
+        file: &models::files::File,
+        content_type: Mime,
+        disposition: DispositionType,
+    ) -> HttpResponse {
+        let chunks = stream::unfold(Some(reader), |reader| async move {
+            let mut reader = reader?;
+            let chunk = web::block(move || {
+                let mut buffer = vec![0; 64 * 1024];
+                let read = reader.read(&mut buffer)?;
+                buffer.truncate(read);
+                Ok::<_, io::Error>((buffer, reader))
+            })
+            .await;
+            match chunk {
+                Ok((buffer, _)) if buffer.is_empty() => None,
+                Ok((buffer, reader)) => Some((Ok(Bytes::from(buffer)), Some(reader))),
+                Err(e) => Some((Err(Error::from(e)), None)),
             }
-        };
-        while let Some(chunk) = field.next().await {
-            let data = match chunk {
-                Ok(c) => c,
-                Err(_) => {
-                    return Err(HttpResponse::BadRequest()
-                        .body("Invalid multipart data")
-                        .into())
-                }
-            };
+        });
 
-            f = match web::block(move || match f.write_all(&data) {
+        HttpResponse::Ok()
+            .content_type(content_type.to_string())
+            .set(content_disposition(file, disposition))
+            .streaming(chunks)
+    }
+
+    /// Returns the strong entity tag of a file entry
+    ///
+    /// Blobs are named after their digest. Files stored before blobs don't have one, so theirs is
+    /// derived from the size and modification time of the file instead.
+    fn file_etag(file: &models::files::File) -> Option<EntityTag> {
+        match (&file.hash, queries::files::legacy_path(file)) {
+            (Some(h), _) => Some(EntityTag::strong(h.clone())),
+            (None, Some(path)) => {
+                let metadata = fs::metadata(path).ok()?;
+                let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+                Some(EntityTag::strong(format!(
+                    "{:x}-{:x}",
+                    metadata.len(),
+                    modified.as_nanos()
+                )))
+            }
+            (None, None) => None,
+        }
+    }
+
+    /// GET a file entry and serve it
+    pub async fn get(request: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, Error> {
+        let id = resolve_id(&path).await?;
+        let unlock = check_password(&request, &path, id).await?;
+        let usercontent = is_usercontent(request.head());
+        // Protected files are served from the main origin, where they were unlocked
+        if let (Some(url), false, Unlock::Public) = (&CONFIG.usercontent_url, usercontent, &unlock)
+        {
+            let target = request
+                .uri()
+                .path_and_query()
+                .map(|p| p.as_str())
+                .unwrap_or_default();
+            return Ok(HttpResponse::TemporaryRedirect()
+                .header(
+                    "Location",
+                    format!("{}{}", url.trim_end_matches('/'), target),
+                )
+                .finish());
+        }
+        // Conditional requests are answered before counting a view, since nothing is sent
+        let (file, etag) = web::block(move || {
+            queries::files::find_viewable(id).map(|f| {
+                let etag = file_etag(&f);
+                (f, etag)
+            })
+        })
+        .await
+        .map_err(ApiError::from)?;
+        check_expires(file.expires)?;
+        if let Some(etag) = &etag {
+            check_modified(&request, etag, file.created)?;
+        }
+
+        // The last view deletes the entry, so its contents are opened while it's being viewed
+        let viewed = web::block(move || {
+            let (file, opened) = queries::files::view(id, |f| open(f, usercontent))?;
+            check_expires(file.expires)?;
+            let contents = match opened {
+                Some(c) => c?,
+                None => open(&file, usercontent)?,
+            };
+            Ok::<_, ApiError>((file, contents))
+        })
+        .await;
+        match viewed.map_err(ApiError::from) {
+            Ok((file, contents)) => {
+                let mut response = match (contents, &etag) {
+                    (Contents::Local(nf), None) => nf.into_response(&request)?,
+                    (Contents::Local(nf), Some(_)) => nf
+                        .use_etag(false)
+                        .use_last_modified(false)
+                        .into_response(&request)?,
+                    (Contents::Stream(reader, content_type, disposition), _) => {
+                        stream_response(reader, &file, content_type, disposition)
+                    }
+                };
+                response.headers_mut().insert(
+                    header::CONTENT_SECURITY_POLICY,
+                    HeaderValue::from_static(FILE_POLICY),
+                );
+                if let Some(etag) = &etag {
+                    set_validators(&mut response, etag, file.created);
+                }
+                set_unlocked(&mut response, unlock);
+                Ok(response)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Query string of thumbnail requests
+    #[derive(Deserialize)]
+    pub struct ThumbnailQuery {
+        /// Maximum width and height of the thumbnail in pixels
+        size: Option<u32>,
+    }
+
+    /// Reads the contents of a file entry to generate a thumbnail from
+    fn read_source(file: &models::files::File) -> Result<Vec<u8>, ApiError> {
+        let reader = match (&file.hash, queries::files::legacy_path(file)) {
+            (Some(h), _) => STORAGE.get(&queries::files::blob_key(h)),
+            (None, Some(path)) => File::open(path).map(|f| Box::new(f) as Box<dyn Read + Send>),
+            (None, None) => return Err(ApiError::NotFound),
+        };
+        let mut source = Vec::new();
+        match reader.and_then(|r| {
+            r.take(thumbnail::MAX_SOURCE_SIZE + 1)
+                .read_to_end(&mut source)
+        }) {
+            Ok(_) if source.len() as u64 > thumbnail::MAX_SOURCE_SIZE => Err(
+                ApiError::PayloadTooLarge("Image is too large for a thumbnail".to_owned()),
+            ),
+            Ok(_) => Ok(source),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(ApiError::NotFound),
+            Err(e) => Err(ApiError::internal(e)),
+        }
+    }
+
+    /// Returns a thumbnail of a file entry as a PNG, generating it if it isn't stored yet
+    fn load_thumbnail(file: &models::files::File, size: u32) -> Result<Vec<u8>, ApiError> {
+        // Thumbnails are deleted along with the blob or legacy file they were generated from
+        let key = match &file.hash {
+            Some(h) => queries::files::thumbnail_key(h, size),
+            None => queries::files::legacy_thumbnail_key(file.id, size),
+        };
+        let mut thumbnail = Vec::new();
+        match STORAGE
+            .get(&key)
+            .and_then(|mut r| r.read_to_end(&mut thumbnail))
+        {
+            Ok(_) => return Ok(thumbnail),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(ApiError::internal(e)),
+        }
+
+        let png = match thumbnail::generate(&read_source(file)?, size) {
+            Ok(png) => png,
+            Err(ImageError::Limits(_)) => {
+                return Err(ApiError::PayloadTooLarge(
+                    "Image is too large for a thumbnail".to_owned(),
+                ))
+            }
+            Err(_) => {
+                return Err(ApiError::UnsupportedMediaType(
+                    "Can't generate a thumbnail of this file".to_owned(),
+                ))
+            }
+        };
+        let staging_dir = CONFIG.files_dir.join(".tmp");
+        let staged = staging_dir.join(format!(
+            "{}.{}",
+            radix_fmt::radix_36(Utc::now().timestamp()),
+            radix_fmt::radix_36(rand::random::<u32>())
+        ));
+        // The thumbnail is still served if it can't be stored
+        let stored = fs::create_dir_all(&staging_dir)
+            .and_then(|_| fs::write(&staged, &png))
+            .and_then(|_| STORAGE.put(&key, &staged));
+        if let Err(e) = stored {
+            eprintln!("Can't store thumbnail: {}", e);
+            let _ = fs::remove_file(&staged);
+        }
+        Ok(png)
+    }
+
+    /// GET a thumbnail of an image file entry
+    pub async fn thumbnail(
+        request: HttpRequest,
+        path: web::Path<String>,
+        query: web::Query<ThumbnailQuery>,
+    ) -> Result<HttpResponse, Error> {
+        let id = resolve_id(&path).await?;
+        let unlock = check_password(&request, &path, id).await?;
+        let file = web::block(move || queries::files::find(id))
+            .await
+            .map_err(ApiError::from)?;
+        // Thumbnails aren't counted as views, so entries with a view limit don't have any
+        if file.deleted_at.is_some() || file.views_left.is_some() {
+            return Err(ApiError::NotFound.into());
+        }
+        check_expires(file.expires)?;
+        let content_type = file_type(&file);
+        let essence = format!("{}/{}", content_type.type_(), content_type.subtype());
+        if !thumbnail::supports(&essence) {
+            return Err(ApiError::UnsupportedMediaType(
+                "Thumbnails can only be generated from images".to_owned(),
+            )
+            .into());
+        }
+
+        let size = thumbnail::round_size(query.size.unwrap_or(thumbnail::DEFAULT_SIZE));
+        let etag = file
+            .hash
+            .as_ref()
+            .map(|h| EntityTag::strong(format!("{}-{}", h, size)));
+        if let Some(etag) = &etag {
+            check_modified(&request, etag, file.created)?;
+        }
+        let created = file.created;
+        let png = match web::block(move || load_thumbnail(&file, size)).await {
+            Ok(png) => png,
+            Err(BlockingError::Error(e)) => return Err(e.into()),
+            Err(BlockingError::Canceled) => {
+                return Err(ApiError::internal("Thumbnail generation was canceled").into())
+            }
+        };
+
+        let mut response = HttpResponse::Ok().content_type("image/png").body(png);
+        if let Some(etag) = &etag {
+            set_validators(&mut response, etag, created);
+        }
+        set_unlocked(&mut response, unlock);
+        Ok(response)
+    }
+
+    /// Reads a multipart text field into a string
+    ///
+    /// Text fields only hold options, so they are limited to a few KiB to keep them out of memory.
+    async fn read_field(mut field: Field) -> Result<String, Error> {
+        let mut contents = Vec::new();
+        while let Some(chunk) = field.next().await {
+            match chunk {
+                Ok(c) if contents.len() + c.len() > MAX_FIELD_LENGTH => {
+                    return Err(ApiError::PayloadTooLarge(format!(
+                        "Fields can't be longer than {} bytes",
+                        MAX_FIELD_LENGTH
+                    ))
+                    .into())
+                }
+                Ok(c) => contents.extend_from_slice(&c),
+                Err(_) => {
+                    return Err(ApiError::BadRequest("Invalid multipart data".to_owned()).into())
+                }
+            }
+        }
+        match String::from_utf8(contents) {
+            Ok(c) => Ok(c),
+            Err(_) => Err(ApiError::BadRequest("Invalid multipart data".to_owned()).into()),
+        }
+    }
+
+    /// Largest size a file can be uploaded with, along with the reason it can't be exceeded
+    pub struct UploadLimit {
+        /// Size in bytes
+        pub size: u64,
+        /// Message explaining the limit
+        pub message: String,
+    }
+
+    impl UploadLimit {
+        /// Responds with "413 Payload Too Large" if a size exceeds the limit
+        pub fn check(&self, size: u64) -> Result<(), ApiError> {
+            if size > self.size {
+                Err(ApiError::PayloadTooLarge(self.message.clone()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the strictest limit on the size of the next file a user uploads, if any
+    pub async fn upload_limit(user: &AuthUser) -> Result<Option<UploadLimit>, Error> {
+        let limits = &CONFIG.limits;
+        let mut candidates = Vec::new();
+        if limits.max_file_size > 0 {
+            candidates.push(UploadLimit {
+                size: limits.max_file_size,
+                message: format!("Files can't be larger than {} bytes", limits.max_file_size),
+            });
+        }
+        if limits.quota > 0 {
+            let used = web::block(|| queries::files::used_storage(None))
+                .await
+                .map_err(ApiError::from)?;
+            candidates.push(UploadLimit {
+                size: limits.quota.saturating_sub(used),
+                message: "Storage quota exceeded".to_owned(),
+            });
+        }
+        if limits.user_quota > 0 {
+            let owner = user.username.clone();
+            let used = web::block(move || queries::files::used_storage(Some(&owner)))
+                .await
+                .map_err(ApiError::from)?;
+            candidates.push(UploadLimit {
+                size: limits.user_quota.saturating_sub(used),
+                message: "User storage quota exceeded".to_owned(),
+            });
+        }
+        Ok(candidates.into_iter().min_by_key(|l| l.size))
+    }
+
+    /// Responds with "415 Unsupported Media Type" if files of a MIME type can't be uploaded
+    pub fn check_type(content_type: &str) -> Result<(), ApiError> {
+        if CONFIG.limits.allows_type(content_type) {
+            Ok(())
+        } else {
+            Err(ApiError::UnsupportedMediaType(format!(
+                "Files of type {} aren't allowed",
+                content_type
+            )))
+        }
+    }
+
+    /// Writes a multipart file field to a staging file, enforcing upload limits as it's received
+    ///
+    /// Returns the hex encoded BLAKE3 digest, the MIME type and the size of the contents.
+    async fn write_field(
+        mut field: Field,
+        path: PathBuf,
+        filepath: &str,
+        limit: Option<&UploadLimit>,
+    ) -> Result<(String, String, i64), Error> {
+        let mut f = match web::block(move || File::create(&path)).await {
+            Ok(f) => f,
+            Err(e) => return Err(ApiError::internal(e).into()),
+        };
+        let detect_type = |head: &[u8]| {
+            let content_type = sniff::content_type(head, filepath);
+            check_type(&content_type).map(|_| content_type)
+        };
+
+        let mut hasher = blake3::Hasher::new();
+        let mut head = Vec::new();
+        let mut content_type = None;
+        let mut size = 0;
+        while let Some(chunk) = field.next().await {
+            let data = match chunk {
+                Ok(c) => c,
+                Err(_) => {
+                    return Err(ApiError::BadRequest("Invalid multipart data".to_owned()).into())
+                }
+            };
+            size += data.len() as u64;
+            if let Some(l) = limit {
+                l.check(size)?;
+            }
+            // The type is checked as soon as enough was received to detect it
+            if content_type.is_none() {
+                let missing = sniff::HEAD_SIZE - head.len();
+                head.extend_from_slice(&data[..data.len().min(missing)]);
+                if head.len() == sniff::HEAD_SIZE {
+                    content_type = Some(detect_type(&head)?);
+                }
+            }
+            hasher.update(&data);
+
+            f = match web::block(move || match f.write_all(&data) {
                 Ok(_) => Ok(f),
                 Err(_) => Err(()),
             })
             .await
             {
                 Ok(f) => f,
-                Err(_) => {
-                    return Err(HttpResponse::InternalServerError()
-                        .body("Internal server error")
-                        .into())
+                Err(e) => return Err(ApiError::internal(e).into()),
+            };
+        }
+
+        let content_type = match content_type {
+            Some(t) => t,
+            None => detect_type(&head)?,
+        };
+        Ok((
+            hasher.finalize().to_hex().to_string(),
+            content_type,
+            size as i64,
+        ))
+    }
+
+    /// Common code for PUT and POST routes
+    async fn put_post(
+        id: i32,
+        slug: Option<String>,
+        body: Multipart,
+        user: AuthUser,
+    ) -> Result<HttpResponse, Error> {
+        let staging_dir = CONFIG.files_dir.join(".tmp");
+        let staged = staging_dir.join(format!(
+            "{}.{}",
+            radix_fmt::radix_36(Utc::now().timestamp()),
+            radix_fmt::radix_36(rand::random::<u32>())
+        ));
+        if let Err(e) = web::block(move || fs::create_dir_all(staging_dir)).await {
+            return Err(ApiError::internal(e).into());
+        }
+
+        // The staged file is moved to the blobs directory on success and removed otherwise
+        let result = put_post_staged(id, slug, body, user, staged.clone()).await;
+        if staged.exists() {
+            if let Err(e) = fs::remove_file(&staged) {
+                eprintln!("Can't remove staged upload: {}", e);
+            }
+        }
+        result
+    }
+
+    /// Reads a multipart body, staging its file before storing it
+    async fn put_post_staged(
+        id: i32,
+        mut slug: Option<String>,
+        mut body: Multipart,
+        user: AuthUser,
+        staged: PathBuf,
+    ) -> Result<HttpResponse, Error> {
+        let limit = upload_limit(&user).await?;
+        let mut file = None;
+        let mut expires = None;
+        let mut views_left = None;
+        let mut password = None;
+        while let Some(field) = body.next().await {
+            let field = field?;
+            let content_disposition = match field.content_disposition() {
+                Some(cd) => cd,
+                None => {
+                    return Err(
+                        ApiError::BadRequest("Missing content disposition".to_owned()).into(),
+                    )
                 }
             };
+
+            // Only the first file is treated, other fields are options
+            if let Some(name) = content_disposition.get_filename() {
+                if file.is_none() {
+                    let name = name.to_owned();
+                    let filepath = filename::sanitize(&name);
+                    let contents =
+                        write_field(field, staged.clone(), &filepath, limit.as_ref()).await?;
+                    file = Some((name, filepath, contents));
+                }
+            } else if content_disposition.get_name() == Some("expires") {
+                expires = match read_field(field).await?.trim().parse() {
+                    Ok(e) => Some(e),
+                    Err(_) => {
+                        return Err(
+                            ApiError::BadRequest("Invalid expiration date".to_owned()).into()
+                        )
+                    }
+                };
+            } else if content_disposition.get_name() == Some("views") {
+                views_left = match read_field(field).await?.trim().parse() {
+                    Ok(v) => Some(v),
+                    Err(_) => {
+                        return Err(ApiError::BadRequest("Invalid view limit".to_owned()).into())
+                    }
+                };
+                check_views(views_left)?;
+            } else if content_disposition.get_name() == Some("slug") {
+                let s = read_field(field).await?.trim().to_owned();
+                check_slug(s.clone(), id).await?;
+                slug = Some(s);
+            } else if content_disposition.get_name() == Some("password") {
+                password = Some(read_field(field).await?);
+            }
         }
+        let (original_name, filepath, (hash, content_type, size)) = match file {
+            Some(f) => f,
+            None => return Err(ApiError::BadRequest("Missing filename".to_owned()).into()),
+        };
 
-        match_replace_result(
-            web::block(move || queries::files::replace(id, &relative_path)).await,
-            id,
-        )
+        let response_slug = slug.clone();
+        let result = web::block(move || {
+            let password_hash = hash_entry_password(password.as_deref());
+            queries::files::replace(
+                NewFile {
+                    id,
+                    filepath: &filepath,
+                    expires,
+                    views_left,
+                    owner: Some(&user.username),
+                    slug: slug.as_deref(),
+                    hash: Some(&hash),
+                    password_hash: password_hash.as_deref(),
+                    original_name: Some(&original_name),
+                    content_type: Some(&content_type),
+                    size: Some(size),
+                },
+                &staged,
+            )
+        })
+        .await;
+        match result {
+            Err(BlockingError::Error(StoreError::QuotaExceeded(m))) => {
+                Err(ApiError::PayloadTooLarge(m.to_owned()).into())
+            }
+            r => match_replace_result(r, id, response_slug),
+        }
     }
 
     /// PUT a new file entry
@@ -398,9 +1605,10 @@ pub mod files {
         body: Multipart,
         identity: Identity,
     ) -> Result<HttpResponse, Error> {
-        auth(identity, request).await?;
-        let id = parse_id(&path)?;
-        put_post(id, body).await
+        let user = auth(identity, request, Some(Scope::FilesWrite)).await?;
+        let (id, slug) = resolve_put(&path).await?;
+        check_owner(id, &user).await?;
+        put_post(id, slug, body, user).await
     }
 
     /// POST a new file entry using a multipart body
@@ -409,34 +1617,61 @@ pub mod files {
         body: Multipart,
         identity: Identity,
     ) -> Result<HttpResponse, Error> {
-        auth(identity, request).await?;
+        let user = auth(identity, request, Some(Scope::FilesWrite)).await?;
         let id = random_id().await?;
-        put_post(id, body).await
+        put_post(id, None, body, user).await
     }
 }
 
 pub mod links {
+    use crate::models::tokens::Scope;
     use crate::{
+        models::links::NewLink,
         queries::{self, SelectQuery},
         routes::{
-            auth, match_find_error, match_replace_result, parse_id, timestamp_to_last_modified,
+            auth, check_expires, check_modified, check_views, content_etag, hash_entry_password,
+            match_find_error, match_replace_result, set_unlocked, set_validators, AuthUser,
         },
     };
     use actix_identity::Identity;
     use actix_web::{web, Error, HttpRequest, HttpResponse};
 
-    select!(links);
+    select!(links, Scope::LinksRead);
     delete!(links);
+    trash!(links);
     random_id!(links);
+    resolve!(links);
+    check_owner!(links);
+    protect!(links, "l");
 
     /// GET a link entry and redirect to it
-    pub async fn get(path: web::Path<String>) -> Result<HttpResponse, Error> {
-        let id = parse_id(&path)?;
-        match web::block(move || queries::links::find(id)).await {
-            Ok(link) => Ok(HttpResponse::Found()
-                .header("Location", link.forward)
-                .header("Last-Modified", timestamp_to_last_modified(link.created))
-                .finish()),
+    pub async fn get(request: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, Error> {
+        let id = resolve_id(&path).await?;
+        let unlock = check_password(&request, &path, id).await?;
+        // Conditional requests are answered before counting a view, since nothing is sent
+        let link = match web::block(move || queries::links::find_viewable(id)).await {
+            Ok(l) => l,
+            Err(e) => return match_find_error(e),
+        };
+        check_expires(link.expires)?;
+        check_modified(
+            &request,
+            &content_etag(link.forward.as_bytes()),
+            link.created,
+        )?;
+
+        match web::block(move || queries::links::view(id)).await {
+            Ok(link) => {
+                check_expires(link.expires)?;
+                let etag = content_etag(link.forward.as_bytes());
+
+                let mut response = HttpResponse::Found()
+                    .header("Location", link.forward)
+                    .finish();
+                set_validators(&mut response, &etag, link.created);
+                set_unlocked(&mut response, unlock);
+                Ok(response)
+            }
             Err(e) => match_find_error(e),
         }
     }
@@ -445,6 +1680,45 @@ pub mod links {
     #[derive(Deserialize)]
     pub struct PutPostLink {
         pub forward: String,
+        pub expires: Option<i32>,
+        pub views: Option<i32>,
+        pub slug: Option<String>,
+        pub password: Option<String>,
+    }
+
+    /// Common code for PUT and POST routes
+    async fn put_post(
+        id: i32,
+        slug: Option<String>,
+        body: web::Json<PutPostLink>,
+        user: AuthUser,
+    ) -> Result<HttpResponse, Error> {
+        check_views(body.views)?;
+        let slug = match &body.slug {
+            Some(s) => {
+                check_slug(s.clone(), id).await?;
+                Some(s.clone())
+            }
+            None => slug,
+        };
+        let response_slug = slug.clone();
+        match_replace_result(
+            web::block(move || {
+                let password_hash = hash_entry_password(body.password.as_deref());
+                queries::links::replace(NewLink {
+                    id,
+                    forward: &body.forward,
+                    expires: body.expires,
+                    views_left: body.views,
+                    owner: Some(&user.username),
+                    slug: slug.as_deref(),
+                    password_hash: password_hash.as_deref(),
+                })
+            })
+            .await,
+            id,
+            response_slug,
+        )
     }
 
     /// PUT a new link entry
@@ -454,12 +1728,10 @@ pub mod links {
         body: web::Json<PutPostLink>,
         identity: Identity,
     ) -> Result<HttpResponse, Error> {
-        auth(identity, request).await?;
-        let id = parse_id(&path)?;
-        match_replace_result(
-            web::block(move || queries::links::replace(id, &body.forward)).await,
-            id,
-        )
+        let user = auth(identity, request, Some(Scope::LinksWrite)).await?;
+        let (id, slug) = resolve_put(&path).await?;
+        check_owner(id, &user).await?;
+        put_post(id, slug, body, user).await
     }
 
     /// POST a new link entry
@@ -468,73 +1740,488 @@ pub mod links {
         body: web::Json<PutPostLink>,
         identity: Identity,
     ) -> Result<HttpResponse, Error> {
-        auth(identity, request).await?;
+        let user = auth(identity, request, Some(Scope::LinksWrite)).await?;
         let id = random_id().await?;
-        match_replace_result(
-            web::block(move || queries::links::replace(id, &body.forward)).await,
-            id,
-        )
+        put_post(id, None, body, user).await
     }
 }
 
 pub mod texts {
-    use crate::routes::escape_html;
+    use crate::models::tokens::Scope;
+    use crate::routes::{escape_html, set_validators};
     use crate::{
-        globals::CONFIG,
-        routes::{HIGHLIGHT_CONTENTS, HIGHLIGHT_LANGUAGE},
+        envelope,
+        error::ApiError,
+        globals::{CONFIG, HIGHLIGHTER},
+        markdown,
+        routes::{DECRYPT_CONTENTS, HIGHLIGHT_CONTENTS, MARKDOWN_CONTENTS},
     };
     use crate::{
+        models::{
+            text_revisions::TextRevision,
+            texts::{Format, NewText, Text},
+        },
         queries::{self, SelectQuery},
         routes::{
-            auth, match_find_error, match_replace_result, parse_id, timestamp_to_last_modified,
+            auth, check_expires, check_modified, check_views, content_etag, hash_entry_password,
+            match_find_error, match_replace_result, path_key, ranged_response, set_unlocked,
+            AuthUser,
         },
     };
     use actix_identity::Identity;
-    use actix_web::{web, Error, HttpRequest, HttpResponse};
+    use actix_web::{
+        http::header::{ContentDisposition, DispositionParam, DispositionType},
+        web::{self, Bytes},
+        Error, HttpRequest, HttpResponse,
+    };
+    use serde::de::DeserializeOwned;
+    use similar::TextDiff;
 
-    select!(texts);
+    select!(texts, Scope::TextsRead);
     delete!(texts);
+    trash!(texts);
     random_id!(texts);
+    resolve!(texts);
+    check_owner!(texts);
+    protect!(texts, "t");
 
-    /// GET a text entry and display it
-    pub async fn get(path: web::Path<String>) -> Result<HttpResponse, Error> {
-        let id = parse_id(&path)?;
-        match web::block(move || queries::texts::find(id)).await {
+    /// Ways a text entry can be displayed
+    #[derive(Clone, Copy)]
+    enum View {
+        /// Depends on the format of the entry, or highlighted if the URL has a file extension
+        Default,
+        /// Contents as plain text
+        Raw,
+        /// Contents as a plain text attachment
+        Download,
+        /// Contents highlighted as HTML
+        Highlight,
+        /// Contents rendered from Markdown to sanitized HTML
+        Markdown,
+        /// Page decrypting encrypted contents in the browser
+        Decrypt,
+    }
+
+    impl View {
+        /// Parses a view from its name, as used in query strings and path suffixes
+        fn from_name(name: &str) -> Option<Self> {
+            match name {
+                "raw" => Some(View::Raw),
+                "download" => Some(View::Download),
+                "highlight" => Some(View::Highlight),
+                "markdown" => Some(View::Markdown),
+                _ => None,
+            }
+        }
+
+        /// Returns the first view named in a query string such as `?raw`
+        fn from_query(query: &str) -> Self {
+            query
+                .split('&')
+                .find_map(|p| View::from_name(p.split('=').next().unwrap_or_default()))
+                .unwrap_or(View::Default)
+        }
+    }
+
+    /// Query string selecting a version of a text
+    #[derive(Deserialize)]
+    pub struct RevisionQuery {
+        pub rev: Option<i32>,
+    }
+
+    /// Query string selecting the versions of a text to compare
+    #[derive(Deserialize)]
+    pub struct DiffQuery {
+        pub from: Option<i32>,
+        pub to: Option<i32>,
+    }
+
+    /// Version of a text listed in its history
+    #[derive(Serialize)]
+    struct RevisionInfo {
+        revision: i32,
+        created: i32,
+        author: Option<String>,
+        size: usize,
+    }
+
+    /// Parses the query string of a request, responding with "400 Bad Request" if it's invalid
+    fn parse_query<T: DeserializeOwned>(request: &HttpRequest) -> Result<T, ApiError> {
+        match web::Query::<T>::from_query(request.query_string()) {
+            Ok(query) => Ok(query.into_inner()),
+            Err(_) => Err(ApiError::BadRequest("Invalid revision".to_owned())),
+        }
+    }
+
+    /// Returns a previous version of a text entry, or `None` if the revision is the current one
+    async fn find_revision(id: i32, revision: i32) -> Result<Option<TextRevision>, Error> {
+        let result = web::block(move || {
+            if revision == queries::texts::current_revision(id)? {
+                Ok(None)
+            } else {
+                queries::texts::revision(id, revision).map(Some)
+            }
+        })
+        .await;
+        match result {
+            Ok(r) => Ok(r),
+            Err(e) => match_find_error(e),
+        }
+    }
+
+    /// SELECT a text entry which can be viewed, without counting a view
+    async fn find_text(id: i32) -> Result<Text, Error> {
+        match web::block(move || queries::texts::find_viewable(id)).await {
             Ok(text) => {
-                let last_modified = timestamp_to_last_modified(text.created);
-                if text.highlight {
-                    let languages: Vec<String> = CONFIG
-                        .highlight
-                        .languages
-                        .iter()
-                        .map(|l| HIGHLIGHT_LANGUAGE.replace("{{ language }}", l))
-                        .collect();
-                    let languages = languages.join("\n");
-                    let contents = HIGHLIGHT_CONTENTS
-                        .replace("{{ title }}", &path)
-                        .replace("{{ theme }}", &CONFIG.highlight.theme)
-                        .replace("{{ contents }}", &escape_html(&text.contents))
-                        .replace("{{ languages }}", &languages);
-
-                    Ok(HttpResponse::Ok()
-                        .header("Last-Modified", last_modified)
-                        .header("Content-Type", "text/html")
-                        .body(contents))
-                } else {
-                    Ok(HttpResponse::Ok()
-                        .header("Last-Modified", last_modified)
-                        .body(text.contents))
-                }
+                check_expires(text.expires)?;
+                Ok(text)
+            }
+            Err(e) => match_find_error(e),
+        }
+    }
+
+    /// SELECT a text entry and count it as a view
+    async fn view_text(id: i32) -> Result<Text, Error> {
+        match web::block(move || queries::texts::view(id)).await {
+            Ok(text) => {
+                check_expires(text.expires)?;
+                Ok(text)
             }
             Err(e) => match_find_error(e),
         }
     }
 
+    /// Common code for GET routes
+    async fn get_text(request: HttpRequest, path: &str, view: View) -> Result<HttpResponse, Error> {
+        let query: RevisionQuery = parse_query(&request)?;
+        let id = resolve_id(path).await?;
+        let unlock = check_password(&request, path, id).await?;
+        let mut text = find_text(id).await?;
+        let limited = text.views_left.is_some();
+        if let Some(revision) = query.rev {
+            if let Some(r) = find_revision(id, revision).await? {
+                text.contents = r.contents;
+                text.format = r.format;
+                text.language = r.language;
+                text.created = r.created;
+            }
+        }
+        let created = text.created;
+        let format = text.format();
+
+        // A known file extension in the URL overrides the language of the text
+        let extension = path
+            .rsplit_once('.')
+            .map(|(_, e)| e.to_owned())
+            .filter(|e| HIGHLIGHTER.find_syntax(e).is_some());
+        let view = match (view, format) {
+            (View::Highlight, Format::Encrypted) | (View::Markdown, Format::Encrypted) => {
+                return Err(ApiError::BadRequest(
+                    "Encrypted texts can't be rendered on the server".to_owned(),
+                )
+                .into())
+            }
+            (View::Default, Format::Encrypted) => View::Decrypt,
+            (View::Default, _) if extension.is_some() => View::Highlight,
+            (View::Default, Format::Plain) => View::Raw,
+            (View::Default, Format::Code) => View::Highlight,
+            (View::Default, Format::Markdown) => View::Markdown,
+            (v, _) => v,
+        };
+
+        let (body, content_type) = match view {
+            View::Highlight | View::Markdown => {
+                let title = escape_html(path);
+                let page = web::block(move || {
+                    let page = if let View::Markdown = view {
+                        let rendered = markdown::render(&text.contents);
+                        MARKDOWN_CONTENTS
+                            .replace("{{ title }}", &title)
+                            .replace("{{ style }}", HIGHLIGHTER.css())
+                            .replace("{{ toc }}", &rendered.toc)
+                            .replace("{{ contents }}", &rendered.html)
+                    } else {
+                        let language = extension.as_deref().or(text.language.as_deref());
+                        let contents = HIGHLIGHTER
+                            .highlight(&text.contents, language)
+                            .unwrap_or_else(|e| {
+                                eprintln!("Can't highlight text: {}", e);
+                                format!("<pre>{}</pre>", escape_html(&text.contents))
+                            });
+                        HIGHLIGHT_CONTENTS
+                            .replace("{{ title }}", &title)
+                            .replace("{{ contents }}", &contents)
+                    };
+                    Ok::<_, ()>(page)
+                })
+                .await
+                .map_err(|_| ApiError::internal("Rendering was canceled"))?;
+                (page, "text/html; charset=utf-8")
+            }
+            View::Decrypt => {
+                // Envelopes are validated JSON, which can't close the script element it's put in
+                let page = DECRYPT_CONTENTS
+                    .replace("{{ title }}", &escape_html(path))
+                    .replace("{{ envelope }}", &text.contents.replace('<', "\\u003c"));
+                (page, "text/html; charset=utf-8")
+            }
+            _ if format == Format::Encrypted => (text.contents, "application/json"),
+            _ => (text.contents, "text/plain; charset=utf-8"),
+        };
+
+        // Conditional requests are answered before counting a view, since nothing is sent
+        let etag = content_etag(body.as_bytes());
+        check_modified(&request, &etag, created)?;
+        if limited {
+            view_text(id).await?;
+        }
+        let mut response = HttpResponse::Ok();
+        response.header("Content-Type", content_type);
+        if let View::Download = view {
+            let filename = match (path.rsplit_once('.'), format) {
+                (Some(_), _) => path.to_owned(),
+                (None, Format::Encrypted) => format!("{}.json", path),
+                (None, _) => format!("{}.txt", path),
+            };
+            response.set(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(filename)],
+            });
+        }
+        // Entries with a view limit are sent whole, so each view sends all of the contents
+        let mut response = if limited {
+            let mut response = response.body(body);
+            set_validators(&mut response, &etag, created);
+            response
+        } else {
+            ranged_response(&request, response, Bytes::from(body), &etag, created)
+        };
+        set_unlocked(&mut response, unlock);
+        Ok(response)
+    }
+
+    /// GET a text entry and display it, optionally the way named in the query string
+    pub async fn get(request: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, Error> {
+        let view = View::from_query(request.query_string());
+        get_text(request, &path, view).await
+    }
+
+    /// GET a text entry and display it the way named by the path suffix
+    pub async fn get_view(
+        request: HttpRequest,
+        path: web::Path<(String, String)>,
+    ) -> Result<HttpResponse, Error> {
+        let view = match View::from_name(&path.1) {
+            Some(v) => v,
+            None => return Err(ApiError::NotFound.into()),
+        };
+        get_text(request, &path.0, view).await
+    }
+
+    /// GET the versions of a text entry, oldest first
+    pub async fn revisions(
+        request: HttpRequest,
+        path: web::Path<String>,
+        identity: Identity,
+    ) -> Result<HttpResponse, Error> {
+        let user = auth(identity, request, Some(Scope::TextsRead)).await?;
+        let id = resolve_id(&path).await?;
+        check_owner(id, &user).await?;
+
+        let result = web::block(move || {
+            let text = queries::texts::find(id)?;
+            let revisions = queries::texts::revisions(id)?;
+            let current = RevisionInfo {
+                revision: revisions.last().map_or(1, |r| r.revision + 1),
+                created: text.created,
+                size: text.contents.len(),
+                author: text.author,
+            };
+            let mut infos: Vec<RevisionInfo> = revisions
+                .into_iter()
+                .map(|r| RevisionInfo {
+                    revision: r.revision,
+                    created: r.created,
+                    size: r.contents.len(),
+                    author: r.author,
+                })
+                .collect();
+            infos.push(current);
+            Ok::<_, diesel::result::Error>(infos)
+        })
+        .await;
+        match result {
+            Ok(infos) => Ok(HttpResponse::Ok().json(infos)),
+            Err(e) => match_find_error(e),
+        }
+    }
+
+    /// GET a unified diff between two versions of a text entry
+    ///
+    /// Compares the current version to the previous one by default.
+    pub async fn diff(
+        request: HttpRequest,
+        path: web::Path<String>,
+    ) -> Result<HttpResponse, Error> {
+        let query: DiffQuery = parse_query(&request)?;
+        let id = resolve_id(&path).await?;
+        let unlock = check_password(&request, &path, id).await?;
+        let text = find_text(id).await?;
+        // Diffs aren't counted as views, so texts with a view limit don't have any
+        if text.views_left.is_some() {
+            return Err(ApiError::NotFound.into());
+        }
+        let current = match web::block(move || queries::texts::current_revision(id)).await {
+            Ok(c) => c,
+            Err(e) => return match_find_error(e),
+        };
+
+        let to = query.to.unwrap_or(current);
+        let from = query.from.unwrap_or(to - 1).max(1);
+        let old = find_revision(id, from).await?;
+        let new = find_revision(id, to).await?;
+        let encrypted = Format::Encrypted.as_str();
+        let compares_encrypted = match (&old, &new) {
+            (Some(o), _) if o.format == encrypted => true,
+            (_, Some(n)) if n.format == encrypted => true,
+            (None, _) | (_, None) => text.format() == Format::Encrypted,
+            _ => false,
+        };
+        if compares_encrypted {
+            return Err(ApiError::BadRequest(
+                "Encrypted texts can't be compared on the server".to_owned(),
+            )
+            .into());
+        }
+        let old = old.map_or_else(|| text.contents.clone(), |r| r.contents);
+        let new = new.map_or_else(|| text.contents.clone(), |r| r.contents);
+
+        let key = path_key(&path).to_owned();
+        let diff = TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .header(
+                &format!("{}?rev={}", key, from),
+                &format!("{}?rev={}", key, to),
+            )
+            .to_string();
+
+        let (body, content_type) = match View::from_query(request.query_string()) {
+            View::Raw | View::Download => (diff, "text/plain; charset=utf-8"),
+            _ => {
+                let title = escape_html(&format!("{} ({}..{})", key, from, to));
+                let page = web::block(move || {
+                    let contents = HIGHLIGHTER
+                        .highlight(&diff, Some("diff"))
+                        .unwrap_or_else(|e| {
+                            eprintln!("Can't highlight diff: {}", e);
+                            format!("<pre>{}</pre>", escape_html(&diff))
+                        });
+                    Ok::<_, ()>(
+                        HIGHLIGHT_CONTENTS
+                            .replace("{{ title }}", &title)
+                            .replace("{{ contents }}", &contents),
+                    )
+                })
+                .await
+                .map_err(|_| ApiError::internal("Rendering was canceled"))?;
+                (page, "text/html; charset=utf-8")
+            }
+        };
+
+        let etag = content_etag(body.as_bytes());
+        check_modified(&request, &etag, text.created)?;
+        let mut response = HttpResponse::Ok();
+        response.header("Content-Type", content_type);
+        let mut response =
+            ranged_response(&request, response, Bytes::from(body), &etag, text.created);
+        set_unlocked(&mut response, unlock);
+        Ok(response)
+    }
+
     /// Request body when PUTting texts
     #[derive(Deserialize)]
     pub struct PutPostText {
         pub contents: String,
+        #[serde(default)]
         pub highlight: bool,
+        pub format: Option<String>,
+        pub expires: Option<i32>,
+        pub views: Option<i32>,
+        pub slug: Option<String>,
+        pub language: Option<String>,
+        pub password: Option<String>,
+    }
+
+    /// Common code for PUT and POST routes
+    async fn put_post(
+        id: i32,
+        slug: Option<String>,
+        body: web::Json<PutPostText>,
+        user: AuthUser,
+    ) -> Result<HttpResponse, Error> {
+        check_views(body.views)?;
+        let max_length = CONFIG.limits.max_text_length;
+        if max_length > 0 && body.contents.len() as u64 > max_length {
+            return Err(ApiError::PayloadTooLarge(format!(
+                "Texts can't be longer than {} bytes",
+                max_length
+            ))
+            .into());
+        }
+        if let Some(language) = &body.language {
+            if HIGHLIGHTER.find_syntax(language).is_none() {
+                return Err(ApiError::BadRequest("Unknown language".to_owned()).into());
+            }
+        }
+        // Texts created before formats existed only tell whether to highlight them
+        let format = match &body.format {
+            Some(f) => f
+                .parse()
+                .map_err(|_| ApiError::BadRequest("Unknown format".to_owned()))?,
+            None if body.highlight => Format::Code,
+            None => Format::Plain,
+        };
+        // The server can't read encrypted texts, only check that they are well-formed
+        let envelope = if format == Format::Encrypted {
+            if body.language.is_some() {
+                return Err(ApiError::BadRequest(
+                    "Encrypted texts can't be highlighted".to_owned(),
+                )
+                .into());
+            }
+            Some(envelope::validate(&body.contents).map_err(ApiError::BadRequest)?)
+        } else {
+            None
+        };
+        let slug = match &body.slug {
+            Some(s) => {
+                check_slug(s.clone(), id).await?;
+                Some(s.clone())
+            }
+            None => slug,
+        };
+        let response_slug = slug.clone();
+        match_replace_result(
+            web::block(move || {
+                let password_hash = hash_entry_password(body.password.as_deref());
+                queries::texts::replace(NewText {
+                    id,
+                    contents: envelope.as_deref().unwrap_or(&body.contents),
+                    highlight: format == Format::Code,
+                    expires: body.expires,
+                    views_left: body.views,
+                    owner: Some(&user.username),
+                    slug: slug.as_deref(),
+                    language: body.language.as_deref(),
+                    format: format.as_str(),
+                    password_hash: password_hash.as_deref(),
+                    author: Some(&user.username),
+                })
+            })
+            .await,
+            id,
+            response_slug,
+        )
     }
 
     /// PUT a new text entry
@@ -544,12 +2231,10 @@ pub mod texts {
         body: web::Json<PutPostText>,
         identity: Identity,
     ) -> Result<HttpResponse, Error> {
-        auth(identity, request).await?;
-        let id = parse_id(&path)?;
-        match_replace_result(
-            web::block(move || queries::texts::replace(id, &body.contents, body.highlight)).await,
-            id,
-        )
+        let user = auth(identity, request, Some(Scope::TextsWrite)).await?;
+        let (id, slug) = resolve_put(&path).await?;
+        check_owner(id, &user).await?;
+        put_post(id, slug, body, user).await
     }
 
     /// POST a new text entry
@@ -558,11 +2243,485 @@ pub mod texts {
         body: web::Json<PutPostText>,
         identity: Identity,
     ) -> Result<HttpResponse, Error> {
-        auth(identity, request).await?;
+        let user = auth(identity, request, Some(Scope::TextsWrite)).await?;
         let id = random_id().await?;
-        match_replace_result(
-            web::block(move || queries::texts::replace(id, &body.contents, body.highlight)).await,
-            id,
-        )
+        put_post(id, None, body, user).await
+    }
+}
+
+/// Resumable uploads using the tus protocol
+pub mod uploads {
+    use crate::{
+        error::ApiError,
+        filename,
+        globals::CONFIG,
+        models::{
+            files::NewFile,
+            tokens::Scope,
+            uploads::{NewUpload, Upload},
+        },
+        queries::{self, files::StoreError},
+        routes::{auth, check_views, files, hash_entry_password, match_find_error, AuthUser},
+        sniff,
+    };
+    use actix_identity::Identity;
+    use actix_web::{error::BlockingError, web, Error, HttpRequest, HttpResponse};
+    use futures::StreamExt;
+    use rand::Rng;
+    use std::{
+        collections::{HashMap, HashSet},
+        fs::{self, File, OpenOptions},
+        io::{self, Write},
+        str::FromStr,
+        sync::Mutex,
+    };
+
+    /// Version of the tus protocol implemented
+    const TUS_VERSION: &str = "1.0.0";
+
+    lazy_static! {
+        /// Uploads currently being written to
+        static ref LOCKED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    }
+
+    /// Lock preventing concurrent writes to an upload, released when dropped
+    struct UploadLock(String);
+
+    impl UploadLock {
+        /// Locks an upload, returning `None` if it is already locked
+        fn acquire(id: &str) -> Option<Self> {
+            if LOCKED.lock().unwrap().insert(id.to_owned()) {
+                Some(UploadLock(id.to_owned()))
+            } else {
+                None
+            }
+        }
+    }
+
+    impl Drop for UploadLock {
+        fn drop(&mut self) {
+            LOCKED.lock().unwrap().remove(&self.0);
+        }
+    }
+
+    /// Checks that the client uses the implemented version of the protocol
+    fn check_version(request: &HttpRequest) -> Result<(), HttpResponse> {
+        match request.headers().get("Tus-Resumable") {
+            Some(v) if v == TUS_VERSION => Ok(()),
+            _ => Err(HttpResponse::PreconditionFailed()
+                .header("Tus-Version", TUS_VERSION)
+                .body("Unsupported tus version")),
+        }
+    }
+
+    /// Parses the value of a header
+    fn parse_header<T: FromStr>(request: &HttpRequest, name: &str) -> Option<T> {
+        request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+    }
+
+    /// Parses the `Upload-Metadata` header, made of comma separated keys and base64 encoded values
+    fn parse_metadata(request: &HttpRequest) -> Result<HashMap<String, String>, ApiError> {
+        let header = match request.headers().get("Upload-Metadata") {
+            Some(h) => h.to_str().unwrap_or_default(),
+            None => return Ok(HashMap::new()),
+        };
+
+        let mut metadata = HashMap::new();
+        for pair in header.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut parts = pair.splitn(2, ' ');
+            let key = parts.next().unwrap_or_default();
+            let value = base64::decode(parts.next().unwrap_or_default())
+                .ok()
+                .and_then(|v| String::from_utf8(v).ok());
+            match value {
+                Some(v) => metadata.insert(key.to_owned(), v),
+                None => return Err(ApiError::BadRequest("Invalid upload metadata".to_owned())),
+            };
+        }
+        Ok(metadata)
+    }
+
+    /// Finds an upload and checks that the user is allowed to modify it
+    async fn find_upload(id: String, user: &AuthUser) -> Result<Upload, Error> {
+        match web::block(move || queries::uploads::find(&id)).await {
+            Ok(upload) if user.owns(Some(&upload.owner)) => Ok(upload),
+            Ok(_) => Err(ApiError::Forbidden("Forbidden".to_owned()).into()),
+            Err(e) => match_find_error(e),
+        }
+    }
+
+    /// Returns the number of bytes received for an upload
+    async fn upload_offset(id: &str) -> Result<i64, Error> {
+        let path = queries::uploads::staged_path(id);
+        match web::block(move || fs::metadata(path)).await {
+            Ok(m) => Ok(m.len() as i64),
+            Err(e) => Err(ApiError::internal(e).into()),
+        }
+    }
+
+    /// Deletes an upload which can't become a file entry, responding with the reason
+    async fn reject<T>(id: String, error: ApiError) -> Result<T, Error> {
+        if let Err(e) = web::block(move || queries::uploads::delete(&id)).await {
+            eprintln!("Can't delete rejected upload: {}", e);
+        }
+        Err(error.into())
+    }
+
+    /// Turns a fully received upload into a file entry, returning its URL path
+    async fn finish(upload: Upload, user: &AuthUser) -> Result<String, Error> {
+        let staged = queries::uploads::staged_path(&upload.id);
+        let filepath = filename::sanitize(&upload.filename);
+        let (hash_path, name) = (staged.clone(), filepath.clone());
+        let (hash, (content_type, size)) = match web::block(move || {
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut File::open(&hash_path)?, &mut hasher)?;
+            let description = sniff::describe(&hash_path, &name)?;
+            Ok::<_, io::Error>((hasher.finalize().to_hex().to_string(), description))
+        })
+        .await
+        {
+            Ok(h) => h,
+            Err(e) => return Err(ApiError::internal(e).into()),
+        };
+
+        // Other uploads may have used up the quota since this one was created
+        let rejection = match files::check_type(&content_type) {
+            Ok(()) => files::upload_limit(user)
+                .await?
+                .and_then(|l| l.check(size as u64).err()),
+            Err(e) => Some(e),
+        };
+        if let Some(e) = rejection {
+            return reject(upload.id, e).await;
+        }
+
+        let id = files::random_id().await?;
+        if let Some(slug) = &upload.slug {
+            files::check_slug(slug.clone(), id).await?;
+        }
+        let location = match &upload.slug {
+            Some(slug) => format!("/f/{}", slug),
+            None => format!("/f/{}", radix_fmt::radix_36(id)),
+        };
+
+        let upload_id = upload.id.clone();
+        let result = web::block(move || {
+            queries::files::replace(
+                NewFile {
+                    id,
+                    filepath: &filepath,
+                    expires: upload.expires,
+                    views_left: upload.views_left,
+                    owner: Some(&upload.owner),
+                    slug: upload.slug.as_deref(),
+                    hash: Some(&hash),
+                    password_hash: upload.password_hash.as_deref(),
+                    original_name: Some(&upload.filename),
+                    content_type: Some(&content_type),
+                    size: Some(size),
+                },
+                &staged,
+            )?;
+            Ok(queries::uploads::delete(&upload.id)?)
+        })
+        .await;
+        match result {
+            Ok(()) => Ok(location),
+            Err(BlockingError::Error(StoreError::QuotaExceeded(m))) => {
+                reject(upload_id, ApiError::PayloadTooLarge(m.to_owned())).await
+            }
+            Err(e) => Err(ApiError::internal(e).into()),
+        }
+    }
+
+    /// OPTIONS describing the supported protocol features
+    pub async fn options() -> HttpResponse {
+        let mut response = HttpResponse::NoContent();
+        response
+            .header("Tus-Resumable", TUS_VERSION)
+            .header("Tus-Version", TUS_VERSION)
+            .header("Tus-Extension", "creation,termination");
+        if CONFIG.limits.max_file_size > 0 {
+            response.header("Tus-Max-Size", CONFIG.limits.max_file_size.to_string());
+        }
+        response.finish()
+    }
+
+    /// POST a new upload
+    pub async fn post(request: HttpRequest, identity: Identity) -> Result<HttpResponse, Error> {
+        let user = auth(identity, request.clone(), Some(Scope::FilesWrite)).await?;
+        check_version(&request)?;
+
+        let length = match parse_header::<i64>(&request, "Upload-Length") {
+            Some(l) if l >= 0 => l,
+            _ => return Err(ApiError::BadRequest("Invalid upload length".to_owned()).into()),
+        };
+        if let Some(l) = files::upload_limit(&user).await? {
+            l.check(length as u64)?;
+        }
+        let metadata = parse_metadata(&request)?;
+        let filename = match metadata.get("filename").or_else(|| metadata.get("name")) {
+            Some(f) => f.clone(),
+            None => return Err(ApiError::BadRequest("Missing filename".to_owned()).into()),
+        };
+        let expires = match metadata.get("expires").map(|e| e.trim().parse()) {
+            Some(Ok(e)) => Some(e),
+            Some(Err(_)) => {
+                return Err(ApiError::BadRequest("Invalid expiration date".to_owned()).into())
+            }
+            None => None,
+        };
+        let views_left = match metadata.get("views").map(|v| v.trim().parse()) {
+            Some(Ok(v)) => Some(v),
+            Some(Err(_)) => {
+                return Err(ApiError::BadRequest("Invalid view limit".to_owned()).into())
+            }
+            None => None,
+        };
+        check_views(views_left)?;
+        let slug = metadata.get("slug").map(|s| s.trim().to_owned());
+        let password = metadata.get("password").cloned();
+        if let Some(s) = &slug {
+            files::check_slug(s.clone(), files::random_id().await?).await?;
+        }
+
+        let mut id = [0; 16];
+        rand::thread_rng().fill(&mut id);
+        let id = base64::encode_config(&id, base64::URL_SAFE_NO_PAD);
+        let staged = queries::uploads::staged_path(&id);
+        let location = format!("/f/uploads/{}", id);
+        let result = web::block(move || {
+            if let Some(dir) = staged.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            File::create(&staged).map_err(|e| e.to_string())?;
+            let password_hash = hash_entry_password(password.as_deref());
+            queries::uploads::insert(NewUpload {
+                id: &id,
+                owner: &user.username,
+                length,
+                filename: &filename,
+                expires,
+                views_left,
+                slug: slug.as_deref(),
+                password_hash: password_hash.as_deref(),
+            })
+            .map_err(|e| e.to_string())
+        })
+        .await;
+        match result {
+            Ok(_) => Ok(HttpResponse::Created()
+                .header("Tus-Resumable", TUS_VERSION)
+                .header("Location", location)
+                .finish()),
+            Err(e) => Err(ApiError::internal(e).into()),
+        }
+    }
+
+    /// HEAD an upload to know how much of it was received
+    pub async fn head(
+        request: HttpRequest,
+        path: web::Path<String>,
+        identity: Identity,
+    ) -> Result<HttpResponse, Error> {
+        let user = auth(identity, request.clone(), Some(Scope::FilesWrite)).await?;
+        check_version(&request)?;
+
+        let upload = find_upload(path.into_inner(), &user).await?;
+        let offset = upload_offset(&upload.id).await?;
+        Ok(HttpResponse::Ok()
+            .header("Tus-Resumable", TUS_VERSION)
+            .header("Upload-Offset", offset.to_string())
+            .header("Upload-Length", upload.length.to_string())
+            .header("Cache-Control", "no-store")
+            .finish())
+    }
+
+    /// PATCH an upload, appending a chunk to it
+    pub async fn patch(
+        request: HttpRequest,
+        path: web::Path<String>,
+        mut body: web::Payload,
+        identity: Identity,
+    ) -> Result<HttpResponse, Error> {
+        let user = auth(identity, request.clone(), Some(Scope::FilesWrite)).await?;
+        check_version(&request)?;
+        match request.headers().get("Content-Type") {
+            Some(ct) if ct == "application/offset+octet-stream" => (),
+            _ => {
+                return Err(
+                    ApiError::UnsupportedMediaType("Invalid content type".to_owned()).into(),
+                )
+            }
+        }
+        let offset = match parse_header::<i64>(&request, "Upload-Offset") {
+            Some(o) => o,
+            None => return Err(ApiError::BadRequest("Invalid upload offset".to_owned()).into()),
+        };
+
+        let _lock = match UploadLock::acquire(&path) {
+            Some(l) => l,
+            None => return Err(ApiError::Conflict("Upload in progress".to_owned()).into()),
+        };
+        let upload = find_upload(path.into_inner(), &user).await?;
+        let mut current = upload_offset(&upload.id).await?;
+        if offset != current {
+            return Err(ApiError::Conflict("Mismatched offset".to_owned()).into());
+        }
+
+        let staged = queries::uploads::staged_path(&upload.id);
+        let mut f = match web::block(move || OpenOptions::new().append(true).open(staged)).await {
+            Ok(f) => f,
+            Err(e) => return Err(ApiError::internal(e).into()),
+        };
+        // Chunks are written as soon as they are received so an interrupted request can be resumed
+        while let Some(chunk) = body.next().await {
+            let data = match chunk {
+                Ok(c) => c,
+                Err(_) => return Err(ApiError::BadRequest("Invalid body".to_owned()).into()),
+            };
+            current += data.len() as i64;
+            if current > upload.length {
+                return Err(
+                    ApiError::PayloadTooLarge("Upload exceeds its length".to_owned()).into(),
+                );
+            }
+
+            f = match web::block(move || f.write_all(&data).map(|_| f)).await {
+                Ok(f) => f,
+                Err(e) => return Err(ApiError::internal(e).into()),
+            };
+        }
+
+        let mut response = HttpResponse::NoContent();
+        response
+            .header("Tus-Resumable", TUS_VERSION)
+            .header("Upload-Offset", current.to_string());
+        if current == upload.length {
+            response.header("Content-Location", finish(upload, &user).await?);
+        }
+        Ok(response.finish())
+    }
+
+    /// DELETE an unfinished upload
+    pub async fn delete(
+        request: HttpRequest,
+        path: web::Path<String>,
+        identity: Identity,
+    ) -> Result<HttpResponse, Error> {
+        let user = auth(identity, request.clone(), Some(Scope::FilesWrite)).await?;
+        check_version(&request)?;
+
+        let _lock = match UploadLock::acquire(&path) {
+            Some(l) => l,
+            None => return Err(ApiError::Conflict("Upload in progress".to_owned()).into()),
+        };
+        let upload = find_upload(path.into_inner(), &user).await?;
+        match web::block(move || queries::uploads::delete(&upload.id)).await {
+            Ok(()) => Ok(HttpResponse::NoContent()
+                .header("Tus-Resumable", TUS_VERSION)
+                .finish()),
+            Err(e) => match_find_error(e),
+        }
+    }
+}
+
+pub mod tokens {
+    use crate::{
+        error::ApiError,
+        models::tokens::{NewToken, Scope},
+        queries,
+        routes::{auth, match_find_error, AuthUser},
+        setup,
+    };
+    use actix_identity::Identity;
+    use actix_web::{web, Error, HttpRequest, HttpResponse};
+
+    /// Authenticates a user allowed to manage API tokens
+    async fn tokens_auth(identity: Identity, request: HttpRequest) -> Result<AuthUser, Error> {
+        let user = auth(identity, request, None).await?;
+        if user.token {
+            return Err(
+                ApiError::Forbidden("API tokens can't manage API tokens".to_owned()).into(),
+            );
+        }
+        Ok(user)
+    }
+
+    /// GET all API tokens of the authenticated user
+    pub async fn select(request: HttpRequest, identity: Identity) -> Result<HttpResponse, Error> {
+        let user = tokens_auth(identity, request).await?;
+        let owner = user.owner_filter();
+        match web::block(move || queries::tokens::select(owner.as_deref())).await {
+            Ok(tokens) => Ok(HttpResponse::Ok().json(tokens)),
+            Err(e) => Err(ApiError::internal(e).into()),
+        }
+    }
+
+    /// Request body when POSTing tokens
+    #[derive(Deserialize)]
+    pub struct PostToken {
+        pub name: String,
+        pub scopes: Vec<String>,
+        pub expires: Option<i32>,
+    }
+
+    /// Response body when POSTing tokens, the only time the token itself is visible
+    #[derive(Serialize)]
+    pub struct CreatedToken {
+        pub id: i32,
+        pub token: String,
+    }
+
+    /// POST a new API token
+    pub async fn post(
+        request: HttpRequest,
+        body: web::Json<PostToken>,
+        identity: Identity,
+    ) -> Result<HttpResponse, Error> {
+        let user = tokens_auth(identity, request).await?;
+        let scopes = match Scope::parse_list(&body.scopes.join(",")) {
+            Ok(s) => s,
+            Err(e) => return Err(ApiError::BadRequest(e).into()),
+        };
+
+        let token = setup::generate_token();
+        let token_hash = setup::hash_token(&token);
+        let insert = move || {
+            queries::tokens::insert(NewToken {
+                owner: &user.username,
+                name: &body.name,
+                token_hash: &token_hash,
+                scopes: &scopes,
+                expires: body.expires,
+            })
+        };
+        match web::block(insert).await {
+            Ok(t) => Ok(HttpResponse::Created().json(CreatedToken { id: t.id, token })),
+            Err(e) => Err(ApiError::internal(e).into()),
+        }
+    }
+
+    /// DELETE an API token, revoking it
+    pub async fn delete(
+        request: HttpRequest,
+        path: web::Path<i32>,
+        identity: Identity,
+    ) -> Result<HttpResponse, Error> {
+        let user = tokens_auth(identity, request).await?;
+        let id = path.into_inner();
+        match web::block(move || queries::tokens::find(id)).await {
+            Ok(token) if user.owns(Some(&token.owner)) => (),
+            Ok(_) => return Err(ApiError::Forbidden("Forbidden".to_owned()).into()),
+            Err(e) => return match_find_error(e),
+        }
+        match web::block(move || queries::tokens::delete(id)).await {
+            Ok(()) => Ok(HttpResponse::Ok().body("Revoked")),
+            Err(e) => match_find_error(e),
+        }
     }
 }
@@ -1,8 +1,32 @@
+table! {
+    blobs (hash) {
+        hash -> Text,
+        refcount -> Integer,
+    }
+}
+
+table! {
+    completed_tasks (name) {
+        name -> Text,
+        completed -> Integer,
+    }
+}
+
 table! {
     files (id) {
         id -> Integer,
         filepath -> Text,
         created -> Integer,
+        expires -> Nullable<Integer>,
+        views_left -> Nullable<Integer>,
+        owner -> Nullable<Text>,
+        slug -> Nullable<Text>,
+        hash -> Nullable<Text>,
+        deleted_at -> Nullable<Integer>,
+        password_hash -> Nullable<Text>,
+        original_name -> Nullable<Text>,
+        content_type -> Nullable<Text>,
+        size -> Nullable<BigInt>,
     }
 }
 
@@ -11,6 +35,25 @@ table! {
         id -> Integer,
         forward -> Text,
         created -> Integer,
+        expires -> Nullable<Integer>,
+        views_left -> Nullable<Integer>,
+        owner -> Nullable<Text>,
+        slug -> Nullable<Text>,
+        deleted_at -> Nullable<Integer>,
+        password_hash -> Nullable<Text>,
+    }
+}
+
+table! {
+    text_revisions (id) {
+        id -> Integer,
+        text_id -> Integer,
+        revision -> Integer,
+        contents -> Text,
+        format -> Text,
+        language -> Nullable<Text>,
+        created -> Integer,
+        author -> Nullable<Text>,
     }
 }
 
@@ -20,7 +63,62 @@ table! {
         contents -> Text,
         created -> Integer,
         highlight -> Bool,
+        expires -> Nullable<Integer>,
+        views_left -> Nullable<Integer>,
+        owner -> Nullable<Text>,
+        slug -> Nullable<Text>,
+        language -> Nullable<Text>,
+        format -> Text,
+        deleted_at -> Nullable<Integer>,
+        password_hash -> Nullable<Text>,
+        author -> Nullable<Text>,
+    }
+}
+
+table! {
+    tokens (id) {
+        id -> Integer,
+        owner -> Text,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Text,
+        expires -> Nullable<Integer>,
+        created -> Integer,
+    }
+}
+
+table! {
+    uploads (id) {
+        id -> Text,
+        owner -> Text,
+        length -> BigInt,
+        filename -> Text,
+        expires -> Nullable<Integer>,
+        views_left -> Nullable<Integer>,
+        slug -> Nullable<Text>,
+        created -> Integer,
+        password_hash -> Nullable<Text>,
+    }
+}
+
+table! {
+    users (id) {
+        id -> Integer,
+        username -> Text,
+        password_hash -> Text,
+        admin -> Bool,
+        created -> Integer,
     }
 }
 
-allow_tables_to_appear_in_same_query!(files, links, texts,);
+allow_tables_to_appear_in_same_query!(
+    blobs,
+    completed_tasks,
+    files,
+    links,
+    text_revisions,
+    texts,
+    tokens,
+    uploads,
+    users,
+);
@@ -1,23 +1,45 @@
 //! Utilities used during the initial setup
 
-use crate::{globals::KEY, Pool};
-use actix_web::middleware::Logger;
+use crate::{listen::Listener, Pool};
+use actix_web::{http::Uri, middleware::Logger};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
 use diesel::{
+    connection::SimpleConnection,
     r2d2::{self, ConnectionManager},
     sqlite::SqliteConnection,
 };
+use rand::Rng;
 use std::{env, path::PathBuf};
 
 #[cfg(not(feature = "dev"))]
-use dialoguer::{Confirmation, PasswordInput};
+use crate::{
+    globals::LEGACY_PASSWORD,
+    models::{
+        tokens::{NewToken, Scope},
+        users::NewUser,
+    },
+    queries,
+};
+#[cfg(not(feature = "dev"))]
+use dialoguer::{Confirmation, Input, PasswordInput};
 #[cfg(not(feature = "dev"))]
 use dirs;
 #[cfg(feature = "dev")]
 use dotenv;
+#[cfg(all(unix, not(feature = "dev")))]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 #[cfg(feature = "dev")]
 use std::str::FromStr;
 #[cfg(not(feature = "dev"))]
-use std::{fs, process};
+use std::{
+    fs,
+    io::{self, IsTerminal, Write},
+    path::Path,
+    process,
+};
 #[cfg(not(feature = "dev"))]
 use toml;
 
@@ -41,15 +63,60 @@ fn get_config_path() -> PathBuf {
     get_config_dir().join("config.toml")
 }
 
-/// Returns a path to the bearer token hash
+/// Returns a path to the legacy single password hash
 #[cfg(not(feature = "dev"))]
 pub fn get_password_path() -> PathBuf {
     get_config_dir().join("passwd")
 }
 
-/// Returns the BLAKE2b digest of the input string
-pub fn hash(input: &[u8]) -> Vec<u8> {
-    blake3::keyed_hash(KEY, input).as_bytes().to_vec()
+/// Returns a path to the cookie signing secret
+#[cfg(not(feature = "dev"))]
+pub fn get_secret_path() -> PathBuf {
+    get_config_dir().join("secret")
+}
+
+/// Minimum length of the cookie signing secret in bytes, which cookie keys are derived from
+pub const MIN_SECRET_LENGTH: usize = 32;
+
+/// Generates a new random cookie signing secret
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0; MIN_SECRET_LENGTH];
+    rand::thread_rng().fill(secret.as_mut_slice());
+    secret
+}
+
+/// Returns the Argon2id hash of a password as a PHC string containing its salt and parameters
+pub fn hash_password(password: &[u8]) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password, &salt)
+        .expect("Can't hash password")
+        .to_string()
+}
+
+/// Verifies a password against a stored Argon2 hash
+///
+/// Hashes from older versions used a key generated at build time, so they never verify.
+pub fn verify_password(password: &[u8], hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(h) => Argon2::default().verify_password(password, &h).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Generates a new random API token
+pub fn generate_token() -> String {
+    let mut token = [0; 32];
+    rand::thread_rng().fill(&mut token);
+    format!(
+        "filite_{}",
+        base64::encode_config(&token, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// Returns the hex encoded BLAKE3 digest of an API token
+pub fn hash_token(token: &str) -> String {
+    blake3::hash(token.as_bytes()).to_hex().to_string()
 }
 
 /// Returns an environment variable and panic if it isn't found
@@ -73,25 +140,162 @@ macro_rules! parse_env {
 #[derive(Serialize, Deserialize, Clone)]
 #[cfg_attr(not(feature = "dev"), serde(default))]
 pub struct Config {
-    /// Port to listen on
+    /// Port to listen on when no listen address is given
     pub port: u16,
+    /// Addresses to listen on, either `host:port`, `[ipv6]:port` or `unix:/path/to.sock`
+    pub listen: Vec<String>,
     /// SQLite database connection url
     pub database_url: String,
     /// SQLite database connection pool size
     pub pool_size: u32,
     /// Directory where to store static files
     pub files_dir: PathBuf,
-    /// Highlight.js configuration
+    /// Interval between cleanups of expired entries, in seconds (0 to disable)
+    pub reap_interval: u64,
+    /// How long deleted entries stay in the trash, in seconds (0 to delete them immediately)
+    pub trash_retention: u64,
+    /// Words that can't be used as slugs, compared case-insensitively
+    pub reserved_slugs: Vec<String>,
+    /// URL of a separate origin serving files, such as `https://usercontent.example.com`, if any
+    pub usercontent_url: Option<String>,
+    /// Unix domain socket configuration
+    pub socket: SocketConfig,
+    /// Storage backend configuration
+    pub storage: StorageConfig,
+    /// Upload limits and storage quotas
+    pub limits: LimitsConfig,
+    /// Syntax highlighting configuration
     pub highlight: HighlightConfig,
+    /// TLS configuration, HTTPS is disabled if absent
+    pub tls: Option<TlsConfig>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 #[cfg_attr(not(feature = "dev"), serde(default))]
 pub struct HighlightConfig {
-    /// Theme to use
+    /// Theme to use, either a default one or one from `themes_dir`
     pub theme: String,
-    /// Additional languages to include
-    pub languages: Vec<String>,
+    /// Directory containing additional `.tmTheme` themes, if any
+    pub themes_dir: Option<PathBuf>,
+    /// Directory containing additional `.sublime-syntax` languages, if any
+    pub syntaxes_dir: Option<PathBuf>,
+}
+
+/// Returns the words reserved by default, matching existing routes
+fn default_reserved_slugs() -> Vec<String> {
+    ["config", "id", "logout", "tokens", "trash", "uploads"]
+        .iter()
+        .map(|s| (*s).to_owned())
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(not(feature = "dev"), serde(default))]
+pub struct SocketConfig {
+    /// Octal permissions of the socket files, such as `"660"`
+    pub mode: Option<String>,
+    /// Group owning the socket files, either a name or a numeric id
+    pub group: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[cfg_attr(not(feature = "dev"), serde(default))]
+pub struct LimitsConfig {
+    /// Maximum size of uploaded files in bytes (0 for no limit)
+    pub max_file_size: u64,
+    /// Maximum length of texts in bytes (0 for no limit)
+    pub max_text_length: u64,
+    /// MIME types files are allowed to have, such as `image/*` (any type if empty)
+    pub allowed_types: Vec<String>,
+    /// MIME types files aren't allowed to have, taking precedence over allowed ones
+    pub denied_types: Vec<String>,
+    /// Maximum total size of all files in bytes (0 for no quota)
+    pub quota: u64,
+    /// Maximum total size of the files of each user in bytes (0 for no quota)
+    pub user_quota: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: 0,
+            max_text_length: 1024 * 1024,
+            allowed_types: Vec::new(),
+            denied_types: Vec::new(),
+            quota: 0,
+            user_quota: 0,
+        }
+    }
+}
+
+/// Whether a MIME type matches a pattern, either a full type or `type/*`
+fn type_matches(pattern: &str, content_type: &str) -> bool {
+    let pattern = pattern.trim();
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(t) => content_type
+            .split('/')
+            .next()
+            .is_some_and(|c| c.eq_ignore_ascii_case(t)),
+        None => pattern.eq_ignore_ascii_case(content_type),
+    }
+}
+
+impl LimitsConfig {
+    /// Whether files of the given MIME type can be uploaded
+    pub fn allows_type(&self, content_type: &str) -> bool {
+        let content_type = content_type.split(';').next().unwrap_or_default().trim();
+        if self
+            .denied_types
+            .iter()
+            .any(|t| type_matches(t, content_type))
+        {
+            return false;
+        }
+        self.allowed_types.is_empty()
+            || self
+                .allowed_types
+                .iter()
+                .any(|t| type_matches(t, content_type))
+    }
+}
+
+/// Storage backend configuration
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Files stored in `files_dir`
+    #[default]
+    Local,
+    /// Files stored in an S3-compatible object storage bucket
+    S3(S3Config),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct S3Config {
+    /// URL of the S3 endpoint
+    pub endpoint: String,
+    /// Name of the bucket
+    pub bucket: String,
+    /// Region of the bucket
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// Access key ID
+    pub access_key: String,
+    /// Secret access key
+    #[serde(skip_serializing)]
+    pub secret_key: String,
+    /// Whether to use path-style URLs instead of virtual-hosted-style ones
+    #[serde(default)]
+    pub path_style: bool,
+    /// Prefix prepended to the names of objects
+    #[serde(default)]
+    pub prefix: String,
+}
+
+/// Returns the region used by default for S3 buckets
+fn default_s3_region() -> String {
+    "us-east-1".to_owned()
 }
 
 #[cfg(not(feature = "dev"))]
@@ -106,22 +310,44 @@ impl Default for Config {
         };
         let pool_size = std::cmp::max(2, num_cpus::get() as u32 / 2);
         let files_dir = get_data_dir().join("files");
+        let reap_interval = 60;
+        let trash_retention = 30 * 24 * 60 * 60;
 
         Self {
             port,
+            listen: Vec::new(),
             database_url,
             pool_size,
             files_dir,
+            reap_interval,
+            trash_retention,
+            reserved_slugs: default_reserved_slugs(),
+            usercontent_url: None,
+            socket: SocketConfig::default(),
+            storage: StorageConfig::default(),
+            limits: LimitsConfig::default(),
             highlight: HighlightConfig::default(),
+            tls: None,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TlsConfig {
+    /// Path to the PEM encoded certificate chain
+    pub cert: PathBuf,
+    /// Path to the PEM encoded private key
+    pub key: PathBuf,
+    /// Port to listen on for plain HTTP requests to redirect to HTTPS, if any
+    pub redirect_port: Option<u16>,
+}
+
 impl Default for HighlightConfig {
     fn default() -> Self {
         Self {
-            theme: "github".to_owned(),
-            languages: vec!["rust".to_owned()],
+            theme: crate::highlight::DEFAULT_THEME.to_owned(),
+            themes_dir: None,
+            syntaxes_dir: None,
         }
     }
 }
@@ -165,6 +391,30 @@ impl Config {
             }
         }
 
+        if result.listeners().is_err() {
+            return Err("Invalid listen address.");
+        }
+        if result.usercontent_url.is_some() && result.usercontent_host().is_none() {
+            return Err("Invalid usercontent_url.");
+        }
+        if let Some(mode) = &result.socket.mode {
+            if u32::from_str_radix(mode, 8).is_err() {
+                return Err("Invalid socket mode.");
+            }
+        }
+
+        if let Some(dir) = &mut result.highlight.themes_dir {
+            *dir = get_config_dir().join(&dir);
+        }
+        if let Some(dir) = &mut result.highlight.syntaxes_dir {
+            *dir = get_config_dir().join(&dir);
+        }
+
+        if let Some(tls) = &mut result.tls {
+            tls.cert = get_config_dir().join(&tls.cert);
+            tls.key = get_config_dir().join(&tls.key);
+        }
+
         Ok(result)
     }
 
@@ -202,14 +452,58 @@ impl Config {
                     .expect("Invalid FILES_DIR")
             }
         };
+        let reap_interval = parse_env!("REAP_INTERVAL");
+        let trash_retention = env::var("TRASH_RETENTION")
+            .ok()
+            .and_then(|r| r.parse().ok())
+            .unwrap_or(30 * 24 * 60 * 60);
+        let listen = env::var("LISTEN")
+            .map(|l| l.split(',').map(|a| a.trim().to_owned()).collect())
+            .unwrap_or_default();
 
         Self {
             port,
+            listen,
             database_url,
             pool_size,
             files_dir,
+            reap_interval,
+            trash_retention,
+            reserved_slugs: default_reserved_slugs(),
+            usercontent_url: env::var("USERCONTENT_URL").ok(),
+            socket: SocketConfig::default(),
+            storage: StorageConfig::default(),
+            limits: LimitsConfig::default(),
             highlight: HighlightConfig::default(),
+            tls: None,
+        }
+    }
+
+    /// Returns the host, and port if any, of the origin serving files
+    pub fn usercontent_host(&self) -> Option<String> {
+        let url: Uri = self.usercontent_url.as_ref()?.parse().ok()?;
+        url.scheme()?;
+        url.authority().map(|a| a.as_str().to_ascii_lowercase())
+    }
+
+    /// Parses the addresses to listen on, defaulting to `localhost:<port>`
+    pub fn listeners(&self) -> Result<Vec<Listener>, String> {
+        if self.listen.is_empty() {
+            return Ok(vec![Listener::Tcp("localhost".to_owned(), self.port)]);
         }
+        self.listen.iter().map(|l| l.parse()).collect()
+    }
+}
+
+/// Options applied to database connections when they are opened
+#[derive(Debug)]
+struct ConnectionOptions;
+
+impl r2d2::CustomizeConnection<SqliteConnection, r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        // Writes wait for each other instead of failing, since transactions lock the whole database
+        conn.batch_execute("PRAGMA busy_timeout = 5000;")
+            .map_err(r2d2::Error::QueryError)
     }
 }
 
@@ -218,6 +512,7 @@ pub fn create_pool(url: &str, size: u32) -> Pool {
     let manager = ConnectionManager::<SqliteConnection>::new(url);
     r2d2::Pool::builder()
         .max_size(size)
+        .connection_customizer(Box::new(ConnectionOptions))
         .build(manager)
         .expect("Can't create pool")
 }
@@ -232,6 +527,9 @@ pub fn init_logger() {
     env_logger::init();
 }
 
+/// Default format of request logs, which includes request IDs to match them with errors
+const LOG_FORMAT: &str = r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-request-id}o"#;
+
 /// Returns the logger middleware
 pub fn logger_middleware() -> Logger {
     #[cfg(feature = "dev")]
@@ -240,68 +538,40 @@ pub fn logger_middleware() -> Logger {
         if let Ok(format) = env::var("LOG_FORMAT") {
             Logger::new(&format)
         } else {
-            Logger::default()
+            Logger::new(LOG_FORMAT)
         }
     }
 
     #[cfg(not(feature = "dev"))]
     {
-        Logger::default()
+        Logger::new(LOG_FORMAT)
     }
 }
 
+/// Writes the cookie signing secret to a file only its owner can read
+#[cfg(not(feature = "dev"))]
+fn write_secret(path: &Path, secret: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files, and an existing one may be readable by anyone
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(secret)
+}
+
 /// Performs the initial setup
-// mode: 0 = normal, 1 = reset password, 2 = reset everything
 #[cfg(not(feature = "dev"))]
-pub fn init(mode: u8) -> Config {
+pub fn init(reset: bool) -> Config {
     fs::create_dir_all(get_config_dir()).unwrap_or_else(|e| {
         eprintln!("Can't create config directory: {}.", e);
         process::exit(1);
     });
 
-    let password_path = get_password_path();
-    if mode > 0 {
-        let mut password;
-        loop {
-            password = PasswordInput::new()
-                .with_prompt("Enter password")
-                .with_confirmation("Confirm password", "Mismatched passwords")
-                .allow_empty_password(true)
-                .interact()
-                .unwrap_or_else(|e| {
-                    eprintln!("Can't read password: {}", e);
-                    process::exit(1);
-                });
-
-            if !password.is_empty() {
-                break;
-            }
-
-            let keep_empty = Confirmation::new()
-                .with_text("Are you sure you want to leave an empty password? This will disable authentication.")
-                .default(false)
-                .interact()
-                .unwrap_or_else(|e| {
-                    eprintln!("Can't read password: {}", e);
-                    process::exit(1);
-                });
-            if keep_empty {
-                break;
-            }
-        }
-
-        let password_hash = hash(password.as_bytes());
-        fs::write(&password_path, password_hash.as_slice()).unwrap_or_else(|e| {
-            eprintln!("Can't write password: {}", e);
-            process::exit(1);
-        });
-    } else if !get_password_path().exists() {
-        eprintln!("No password file found. Try running `filite init` or `filite passwd`.");
-        process::exit(1);
-    }
-
     let config_path = get_config_path();
-    if mode > 1 {
+    if reset {
         println!("Generating config file at {}", config_path.display());
         let config = Config::default();
         config.write_file().unwrap_or_else(|e| {
@@ -313,11 +583,351 @@ pub fn init(mode: u8) -> Config {
         process::exit(1);
     }
 
-    if mode > 0 {
-        process::exit(0);
+    let secret_path = get_secret_path();
+    if reset || !secret_path.exists() {
+        println!("Generating cookie secret at {}", secret_path.display());
+        write_secret(&secret_path, &generate_secret()).unwrap_or_else(|e| {
+            eprintln!("Can't write cookie secret: {}", e);
+            process::exit(1);
+        });
     }
+
     Config::read_file().unwrap_or_else(|e| {
         eprintln!("{}", e);
         process::exit(1);
     })
 }
+
+/// Prompts for a username
+#[cfg(not(feature = "dev"))]
+fn prompt_username() -> String {
+    Input::<String>::new()
+        .with_prompt("Enter username")
+        .interact()
+        .unwrap_or_else(|e| {
+            eprintln!("Can't read username: {}", e);
+            process::exit(1);
+        })
+}
+
+/// Prompts for a password, allowing it to be empty after confirmation if `allow_empty` is set
+#[cfg(not(feature = "dev"))]
+fn prompt_password(allow_empty: bool) -> String {
+    loop {
+        let password = PasswordInput::new()
+            .with_prompt("Enter password")
+            .with_confirmation("Confirm password", "Mismatched passwords")
+            .allow_empty_password(allow_empty)
+            .interact()
+            .unwrap_or_else(|e| {
+                eprintln!("Can't read password: {}", e);
+                process::exit(1);
+            });
+
+        if !password.is_empty() {
+            return password;
+        }
+
+        let keep_empty = Confirmation::new()
+            .with_text("Are you sure you want to leave an empty password? This will disable authentication.")
+            .default(false)
+            .interact()
+            .unwrap_or_else(|e| {
+                eprintln!("Can't read password: {}", e);
+                process::exit(1);
+            });
+        if keep_empty {
+            return password;
+        }
+    }
+}
+
+/// Creates a user or updates the password of an existing one
+#[cfg(not(feature = "dev"))]
+fn set_user(username: &str, password: &str, admin: bool) {
+    let password_hash = hash_password(password.as_bytes());
+    let new_user = NewUser {
+        username,
+        password_hash: &password_hash,
+        admin,
+    };
+    queries::users::upsert(new_user).unwrap_or_else(|e| {
+        eprintln!("Can't save user: {}", e);
+        process::exit(1);
+    });
+}
+
+/// Runs the `token` subcommand
+#[cfg(not(feature = "dev"))]
+fn token_command<'a>(mut args: impl Iterator<Item = &'a String>, flags: &[&'a String]) {
+    let flag = |name: &str| {
+        let prefix = format!("--{}=", name);
+        flags
+            .iter()
+            .find_map(|f| f.strip_prefix(&prefix))
+            .map(str::to_owned)
+    };
+
+    match args.next().map(String::as_str) {
+        Some("create") => {
+            let (username, name) = match (args.next(), args.next()) {
+                (Some(u), Some(n)) => (u, n),
+                _ => {
+                    eprintln!("Usage: filite token create <username> <name> --scopes=<scopes> [--expires=<timestamp>]");
+                    process::exit(1);
+                }
+            };
+            let scopes =
+                Scope::parse_list(&flag("scopes").unwrap_or_default()).unwrap_or_else(|e| {
+                    eprintln!(
+                        "{}. Available scopes are: {}.",
+                        e,
+                        Scope::ALL
+                            .iter()
+                            .map(|s| s.as_str())
+                            .collect::<Vec<&str>>()
+                            .join(", ")
+                    );
+                    process::exit(1);
+                });
+            let expires = flag("expires").map(|e| {
+                e.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid expiration date.");
+                    process::exit(1);
+                })
+            });
+            if queries::users::find(username).is_err() {
+                eprintln!("No user named `{}`.", username);
+                process::exit(1);
+            }
+
+            let token = generate_token();
+            let token_hash = hash_token(&token);
+            let new_token = NewToken {
+                owner: username,
+                name,
+                token_hash: &token_hash,
+                scopes: &scopes,
+                expires,
+            };
+            queries::tokens::insert(new_token).unwrap_or_else(|e| {
+                eprintln!("Can't save token: {}", e);
+                process::exit(1);
+            });
+            println!("{}", token);
+        }
+        Some("list") => {
+            let tokens =
+                queries::tokens::select(args.next().map(String::as_str)).unwrap_or_else(|e| {
+                    eprintln!("Can't list tokens: {}", e);
+                    process::exit(1);
+                });
+            for token in tokens {
+                let expires = match token.expires {
+                    Some(e) => format!(", expires {}", e),
+                    None => String::new(),
+                };
+                println!(
+                    "{}: {} ({}) [{}]{}",
+                    token.id, token.name, token.owner, token.scopes, expires
+                );
+            }
+        }
+        Some("revoke") => {
+            let id = args.next().and_then(|i| i.parse().ok()).unwrap_or_else(|| {
+                eprintln!("Usage: filite token revoke <id>");
+                process::exit(1);
+            });
+            queries::tokens::delete(id).unwrap_or_else(|e| {
+                eprintln!("Can't revoke token: {}", e);
+                process::exit(1);
+            });
+        }
+        _ => {
+            eprintln!("Usage: filite token <create|list|revoke>");
+            process::exit(1);
+        }
+    }
+}
+
+/// Whether the subcommand passed on the command line is `init`
+///
+/// Only the first argument which isn't a flag is the subcommand, later ones are its arguments.
+#[cfg(not(feature = "dev"))]
+pub fn is_init_command() -> bool {
+    env::args()
+        .skip(1)
+        .find(|a| !a.starts_with("--"))
+        .as_deref()
+        == Some("init")
+}
+
+/// Runs the subcommand passed on the command line, if any
+///
+/// Exits once the subcommand is done, and returns if the server should be started instead.
+#[cfg(not(feature = "dev"))]
+pub fn run_command() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let flags: Vec<&String> = args.iter().filter(|a| a.starts_with("--")).collect();
+    let admin_flag = flags.iter().any(|a| *a == "--admin");
+    let mut args = args.iter().filter(|a| !a.starts_with("--"));
+
+    match args.next().map(String::as_str) {
+        None => return,
+        Some("init") => {
+            println!("Creating the admin user");
+            let username = prompt_username();
+            let password = prompt_password(true);
+            if password.is_empty() {
+                println!("No user created, authentication is disabled until one is.");
+            } else {
+                set_user(&username, &password, true);
+            }
+        }
+        Some("passwd") => {
+            let username = match args.next() {
+                Some(u) => u.to_owned(),
+                None => prompt_username(),
+            };
+            let admin = admin_flag
+                || queries::users::find(&username)
+                    .map(|u| u.admin)
+                    .unwrap_or(false);
+            let password = prompt_password(false);
+            set_user(&username, &password, admin);
+        }
+        Some("userdel") => {
+            let username = match args.next() {
+                Some(u) => u.to_owned(),
+                None => prompt_username(),
+            };
+            queries::users::delete(&username).unwrap_or_else(|e| {
+                eprintln!("Can't delete user: {}", e);
+                process::exit(1);
+            });
+            queries::tokens::delete_owned(&username).unwrap_or_else(|e| {
+                eprintln!("Can't revoke user tokens: {}", e);
+                process::exit(1);
+            });
+        }
+        Some("users") => {
+            let users = queries::users::select().unwrap_or_else(|e| {
+                eprintln!("Can't list users: {}", e);
+                process::exit(1);
+            });
+            for user in users {
+                if user.admin {
+                    println!("{} (admin)", user.username);
+                } else {
+                    println!("{}", user.username);
+                }
+            }
+        }
+        Some("token") => token_command(args, &flags),
+        Some(c) => {
+            eprintln!("Unknown command `{}`.", c);
+            process::exit(1);
+        }
+    }
+    process::exit(0);
+}
+
+/// Checks for a single password set by a version of filite without users
+///
+/// Its hash was keyed with a secret generated when that version was built, so it can't be
+/// verified anymore and has to be replaced by a user. When started from a terminal, filite
+/// asks for that user right away. The password file is removed once a user exists.
+#[cfg(not(feature = "dev"))]
+pub fn check_legacy_password() {
+    if !*LEGACY_PASSWORD {
+        return;
+    }
+    match queries::users::count() {
+        Ok(0) if io::stdin().is_terminal() => {
+            println!(
+                "This instance is protected by a password set by an older version of filite, \
+                 which can't be verified anymore. Create an admin user to replace it."
+            );
+            let username = prompt_username();
+            let password = prompt_password(false);
+            set_user(&username, &password, true);
+            remove_legacy_password();
+        }
+        Ok(0) => eprintln!(
+            "This instance is protected by a password set by an older version of filite, \
+             which can't be verified anymore, so every request requiring authentication is \
+             denied. Run `filite passwd <username> --admin` to create an admin user and unlock \
+             it, or delete {} to disable authentication.",
+            get_password_path().display()
+        ),
+        Ok(_) => remove_legacy_password(),
+        Err(e) => eprintln!("Can't count users: {}", e),
+    }
+}
+
+/// Removes the password file of a version of filite without users
+#[cfg(not(feature = "dev"))]
+fn remove_legacy_password() {
+    if let Err(e) = fs::remove_file(get_password_path()) {
+        eprintln!("Can't remove legacy password file: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Limits allowing and denying the given types
+    fn limits(allowed: &[&str], denied: &[&str]) -> LimitsConfig {
+        LimitsConfig {
+            allowed_types: allowed.iter().map(|t| (*t).to_owned()).collect(),
+            denied_types: denied.iter().map(|t| (*t).to_owned()).collect(),
+            ..LimitsConfig::default()
+        }
+    }
+
+    #[test]
+    fn allows_any_type_by_default() {
+        let limits = limits(&[], &[]);
+        assert!(limits.allows_type("application/x-msdownload"));
+        assert!(limits.allows_type("text/plain"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let limits = limits(&["image/*", "application/pdf"], &[]);
+        assert!(limits.allows_type("image/png"));
+        assert!(limits.allows_type("image/svg+xml"));
+        assert!(limits.allows_type("application/pdf"));
+        assert!(!limits.allows_type("application/zip"));
+        assert!(!limits.allows_type("video/png"));
+        assert!(!limits.allows_type("images/png"));
+
+        let any = self::limits(&["*/*"], &[]);
+        assert!(any.allows_type("application/zip"));
+    }
+
+    #[test]
+    fn ignores_case_and_parameters() {
+        let limits = limits(&["Text/*", " application/JSON "], &[]);
+        assert!(limits.allows_type("text/plain; charset=utf-8"));
+        assert!(limits.allows_type("TEXT/HTML"));
+        assert!(limits.allows_type("Application/Json;charset=UTF-8"));
+        assert!(!limits.allows_type("application/xml"));
+    }
+
+    #[test]
+    fn denied_types_take_precedence() {
+        let limits = limits(&["image/*"], &["image/svg+xml"]);
+        assert!(limits.allows_type("image/png"));
+        assert!(!limits.allows_type("image/svg+xml"));
+        assert!(!limits.allows_type("Image/SVG+XML; charset=utf-8"));
+
+        let limits = self::limits(&[], &["application/*"]);
+        assert!(limits.allows_type("text/plain"));
+        assert!(!limits.allows_type("application/x-msdownload"));
+
+        let limits = self::limits(&["text/plain"], &["text/*"]);
+        assert!(!limits.allows_type("text/plain"));
+    }
+}
@@ -0,0 +1,53 @@
+//! Detection of the type of uploaded contents
+//!
+//! Types are detected from the first bytes of the contents when they have a known signature,
+//! falling back to the extension of the file name.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+/// Number of bytes read from the start of contents to detect their type
+pub const HEAD_SIZE: usize = 8 * 1024;
+/// Type of contents which couldn't be identified
+const DEFAULT_TYPE: &str = "application/octet-stream";
+
+/// Reads the start of some contents
+pub fn read_head(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    reader.take(HEAD_SIZE as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// Whether the start of some contents looks like UTF-8 text
+fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // The head may end in the middle of a character
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// Returns the MIME type of contents given their start and the name of their file
+pub fn content_type(head: &[u8], name: &str) -> String {
+    if let Some(t) = infer::get(head) {
+        return t.mime_type().to_owned();
+    }
+    match mime_guess::from_path(name).first() {
+        Some(t) => t.to_string(),
+        None if !head.is_empty() && is_text(head) => "text/plain".to_owned(),
+        None => DEFAULT_TYPE.to_owned(),
+    }
+}
+
+/// Returns the MIME type and size in bytes of a local file
+pub fn describe(path: &Path, name: &str) -> io::Result<(String, i64)> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len() as i64;
+    Ok((content_type(&read_head(file)?, name), size))
+}
@@ -0,0 +1,259 @@
+//! Backends storing the contents of uploaded files
+
+use crate::setup::{S3Config, StorageConfig};
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Metadata about stored contents
+pub struct Stat {
+    /// Size of the contents in bytes
+    pub size: u64,
+}
+
+/// A backend storing the contents of files under string keys
+pub trait Storage: Send + Sync {
+    /// Stores the contents of a local file under a key, consuming the local file
+    fn put(&self, key: &str, source: &Path) -> io::Result<()>;
+
+    /// Returns a reader streaming the contents stored under a key
+    fn get(&self, key: &str) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Deletes the contents stored under a key, succeeding if there are none
+    fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// Returns metadata about the contents stored under a key, if there are any
+    fn stat(&self, key: &str) -> io::Result<Option<Stat>>;
+
+    /// Returns the path of the contents stored under a key if they live on the local filesystem
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Creates the storage backend described by the config
+pub fn from_config(config: &StorageConfig, files_dir: &Path) -> Result<Box<dyn Storage>, String> {
+    match config {
+        StorageConfig::Local => Ok(Box::new(LocalStorage {
+            root: files_dir.to_owned(),
+        })),
+        StorageConfig::S3(s3) => Ok(Box::new(S3Storage::new(s3)?)),
+    }
+}
+
+/// Storage in a directory of the local filesystem
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl Storage for LocalStorage {
+    fn put(&self, key: &str, source: &Path) -> io::Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Renaming fails across filesystems, in which case the file is copied instead
+        if fs::rename(source, &path).is_err() {
+            fs::copy(source, &path)?;
+            fs::remove_file(source)?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(self.root.join(key))?))
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.root.join(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn stat(&self, key: &str) -> io::Result<Option<Stat>> {
+        match fs::metadata(self.root.join(key)) {
+            Ok(m) => Ok(Some(Stat { size: m.len() })),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.root.join(key))
+    }
+}
+
+/// How long signed S3 requests stay valid
+const S3_SIGNATURE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Storage in a bucket of an S3-compatible object storage service
+pub struct S3Storage {
+    bucket: Bucket,
+    credentials: Credentials,
+    prefix: String,
+    agent: ureq::Agent,
+}
+
+impl S3Storage {
+    /// Creates a backend using the bucket described by the config
+    pub fn new(config: &S3Config) -> Result<Self, String> {
+        let endpoint = config
+            .endpoint
+            .parse()
+            .map_err(|e| format!("Invalid S3 endpoint: {}", e))?;
+        let url_style = if config.path_style {
+            UrlStyle::Path
+        } else {
+            UrlStyle::VirtualHost
+        };
+        let bucket = Bucket::new(
+            endpoint,
+            url_style,
+            config.bucket.clone(),
+            config.region.clone(),
+        )
+        .map_err(|e| format!("Invalid S3 bucket: {}", e))?;
+
+        Ok(Self {
+            bucket,
+            credentials: Credentials::new(config.access_key.clone(), config.secret_key.clone()),
+            prefix: config.prefix.clone(),
+            agent: ureq::Agent::new(),
+        })
+    }
+
+    /// Returns the name of the object storing a key
+    fn object(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+/// Converts an S3 request error to an IO error, keeping track of missing objects
+fn s3_error(error: ureq::Error) -> io::Error {
+    match error {
+        ureq::Error::Status(404, _) => io::Error::new(ErrorKind::NotFound, "object not found"),
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+impl Storage for S3Storage {
+    fn put(&self, key: &str, source: &Path) -> io::Result<()> {
+        let object = self.object(key);
+        let url = self
+            .bucket
+            .put_object(Some(&self.credentials), &object)
+            .sign(S3_SIGNATURE_DURATION);
+        let file = File::open(source)?;
+        let size = file.metadata()?.len();
+        self.agent
+            .put(url.as_str())
+            .set("Content-Length", &size.to_string())
+            .send(file)
+            .map_err(s3_error)?;
+        fs::remove_file(source)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Box<dyn Read + Send>> {
+        let object = self.object(key);
+        let url = self
+            .bucket
+            .get_object(Some(&self.credentials), &object)
+            .sign(S3_SIGNATURE_DURATION);
+        let response = self.agent.get(url.as_str()).call().map_err(s3_error)?;
+        Ok(response.into_reader())
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        let object = self.object(key);
+        let url = self
+            .bucket
+            .delete_object(Some(&self.credentials), &object)
+            .sign(S3_SIGNATURE_DURATION);
+        match self.agent.delete(url.as_str()).call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(e) => Err(s3_error(e)),
+        }
+    }
+
+    fn stat(&self, key: &str) -> io::Result<Option<Stat>> {
+        let object = self.object(key);
+        let url = self
+            .bucket
+            .head_object(Some(&self.credentials), &object)
+            .sign(S3_SIGNATURE_DURATION);
+        match self.agent.head(url.as_str()).call() {
+            Ok(response) => Ok(Some(Stat {
+                size: response
+                    .header("Content-Length")
+                    .and_then(|l| l.parse().ok())
+                    .unwrap_or_default(),
+            })),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(s3_error(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// Reads a required variable of the S3 test environment
+    fn var(name: &str) -> String {
+        env::var(name).unwrap_or_else(|_| panic!("{} must be set to run S3 tests", name))
+    }
+
+    /// Storage in the bucket described by the `FILITE_TEST_S3_*` environment variables
+    fn s3_storage() -> S3Storage {
+        S3Storage::new(&S3Config {
+            endpoint: var("FILITE_TEST_S3_ENDPOINT"),
+            bucket: var("FILITE_TEST_S3_BUCKET"),
+            region: env::var("FILITE_TEST_S3_REGION").unwrap_or_else(|_| "us-east-1".to_owned()),
+            access_key: var("FILITE_TEST_S3_ACCESS_KEY"),
+            secret_key: var("FILITE_TEST_S3_SECRET_KEY"),
+            path_style: true,
+            prefix: format!("filite-test-{}/", std::process::id()),
+        })
+        .unwrap()
+    }
+
+    /// Stores, reads, stats and deletes an object against a real S3 service
+    ///
+    /// See the Testing section of the README to run it against MinIO.
+    #[test]
+    #[ignore]
+    fn s3_round_trip() {
+        let storage = s3_storage();
+        let key = "blobs/round-trip";
+        let contents = b"filite S3 round trip".repeat(1000);
+        let source = env::temp_dir().join(format!("filite-s3-test-{}", std::process::id()));
+        fs::write(&source, &contents).unwrap();
+
+        storage.put(key, &source).unwrap();
+        assert!(!source.exists(), "the local file should be consumed");
+        assert_eq!(
+            storage.stat(key).unwrap().map(|s| s.size),
+            Some(contents.len() as u64)
+        );
+        let mut read = Vec::new();
+        storage.get(key).unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, contents);
+        assert_eq!(storage.local_path(key), None);
+
+        storage.delete(key).unwrap();
+        assert!(storage.stat(key).unwrap().is_none());
+        assert_eq!(
+            storage.get(key).err().map(|e| e.kind()),
+            Some(ErrorKind::NotFound)
+        );
+        // Deleting missing objects succeeds so orphans can be cleaned up more than once
+        storage.delete(key).unwrap();
+    }
+}
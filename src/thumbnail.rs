@@ -0,0 +1,127 @@
+//! Thumbnails of uploaded images
+//!
+//! Thumbnails are generated the first time they are requested and stored next to the blob of
+//! their image, so they are deleted along with it.
+
+use image::{ImageFormat, ImageReader, ImageResult, Limits};
+use std::io::Cursor;
+
+/// Sizes thumbnails are generated with, requested sizes being rounded up to one of them
+pub const SIZES: &[u32] = &[64, 128, 256, 512, 1024];
+/// Size of thumbnails when none is requested
+pub const DEFAULT_SIZE: u32 = 256;
+/// Maximum size of the images thumbnails are generated from, in bytes
+pub const MAX_SOURCE_SIZE: u64 = 32 * 1024 * 1024;
+/// Maximum width and height of the images thumbnails are generated from
+const MAX_DIMENSION: u32 = 16 * 1024;
+/// Maximum memory used to decode an image, in bytes
+const MAX_ALLOC: u64 = 256 * 1024 * 1024;
+/// MIME types of the images thumbnails can be generated from
+const TYPES: &[&str] = &[
+    "image/bmp",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+];
+
+/// Whether thumbnails can be generated from contents of a MIME type
+pub fn supports(content_type: &str) -> bool {
+    TYPES.iter().any(|t| t.eq_ignore_ascii_case(content_type))
+}
+
+/// Rounds a requested size up to the closest size thumbnails are generated with
+pub fn round_size(requested: u32) -> u32 {
+    SIZES
+        .iter()
+        .copied()
+        .find(|s| *s >= requested)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+/// Generates a PNG thumbnail fitting in a square of the given size, never enlarging the image
+pub fn generate(source: &[u8], size: u32) -> ImageResult<Vec<u8>> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_ALLOC);
+
+    let mut reader = ImageReader::new(Cursor::new(source)).with_guessed_format()?;
+    reader.limits(limits);
+    let image = reader.decode()?;
+    let thumbnail = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+
+    let mut png = Vec::new();
+    thumbnail.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, GenericImageView, RgbImage};
+
+    /// Encodes a blank image of the given dimensions as a PNG
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn rounds_sizes_up() {
+        assert_eq!(round_size(0), 64);
+        assert_eq!(round_size(64), 64);
+        assert_eq!(round_size(65), 128);
+        assert_eq!(round_size(300), 512);
+        assert_eq!(round_size(1024), 1024);
+    }
+
+    #[test]
+    fn clamps_sizes() {
+        assert_eq!(round_size(1025), 1024);
+        assert_eq!(round_size(u32::MAX), 1024);
+    }
+
+    #[test]
+    fn supports_images() {
+        assert!(supports("image/png"));
+        assert!(supports("IMAGE/JPEG"));
+        assert!(supports("image/webp"));
+    }
+
+    #[test]
+    fn rejects_unsupported_types() {
+        assert!(!supports("image/svg+xml"));
+        assert!(!supports("image/tiff"));
+        assert!(!supports("text/plain"));
+        assert!(!supports("image/png; charset=utf-8"));
+        assert!(!supports(""));
+    }
+
+    #[test]
+    fn shrinks_large_images() {
+        let thumbnail = generate(&png(200, 100), 64).unwrap();
+        assert_eq!(image::guess_format(&thumbnail).unwrap(), ImageFormat::Png);
+        let image = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!(image.dimensions(), (64, 32));
+    }
+
+    #[test]
+    fn keeps_small_images() {
+        let thumbnail = generate(&png(40, 20), 64).unwrap();
+        let image = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!(image.dimensions(), (40, 20));
+    }
+
+    #[test]
+    fn rejects_invalid_images() {
+        assert!(generate(b"not an image", 64).is_err());
+    }
+}
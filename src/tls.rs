@@ -0,0 +1,108 @@
+//! TLS termination using rustls
+
+use crate::setup::TlsConfig;
+use rustls::{
+    internal::pemfile,
+    sign::{self, CertifiedKey},
+    NoClientAuth, ResolvesServerCert, ServerConfig, SignatureScheme,
+};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+/// Certificate resolver whose certificate can be swapped while the server is running
+pub struct CertResolver {
+    certified_key: RwLock<CertifiedKey>,
+}
+
+impl CertResolver {
+    /// Creates a resolver serving the certificate described by the config
+    pub fn new(config: &TlsConfig) -> Result<Self, String> {
+        Ok(Self {
+            certified_key: RwLock::new(load_certified_key(&config.cert, &config.key)?),
+        })
+    }
+
+    /// Reloads the certificate from disk, keeping the previous one if the new one is invalid
+    pub fn reload(&self, config: &TlsConfig) -> Result<(), String> {
+        let certified_key = load_certified_key(&config.cert, &config.key)?;
+        *self.certified_key.write().unwrap() = certified_key;
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(
+        &self,
+        _server_name: Option<webpki::DNSNameRef>,
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<CertifiedKey> {
+        Some(self.certified_key.read().unwrap().clone())
+    }
+}
+
+/// Reads a PEM encoded certificate chain and private key
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, String> {
+    let cert_file =
+        File::open(cert_path).map_err(|e| format!("Can't open {}: {}", cert_path.display(), e))?;
+    let certs = pemfile::certs(&mut BufReader::new(cert_file))
+        .map_err(|_| format!("Invalid certificate in {}", cert_path.display()))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", cert_path.display()));
+    }
+
+    // Keys can either be PKCS8 or RSA encoded
+    let read_keys = |pkcs8: bool| {
+        let key_file = File::open(key_path)
+            .map_err(|e| format!("Can't open {}: {}", key_path.display(), e))?;
+        let mut reader = BufReader::new(key_file);
+        let keys = if pkcs8 {
+            pemfile::pkcs8_private_keys(&mut reader)
+        } else {
+            pemfile::rsa_private_keys(&mut reader)
+        };
+        keys.map_err(|_| format!("Invalid private key in {}", key_path.display()))
+    };
+    let mut keys = read_keys(true)?;
+    if keys.is_empty() {
+        keys = read_keys(false)?;
+    }
+    let key = match keys.into_iter().next() {
+        Some(k) => k,
+        None => return Err(format!("No private key found in {}", key_path.display())),
+    };
+    let signing_key = sign::any_supported_type(&key)
+        .map_err(|_| format!("Unsupported private key in {}", key_path.display()))?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(signing_key)))
+}
+
+/// Creates a rustls server config using the given certificate resolver
+pub fn server_config(resolver: Arc<CertResolver>) -> ServerConfig {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = resolver;
+    config
+}
+
+/// Reloads the certificate every time a SIGHUP signal is received
+#[cfg(unix)]
+pub async fn reload_on_sighup(resolver: Arc<CertResolver>, config: TlsConfig) {
+    use actix_rt::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Can't listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match resolver.reload(&config) {
+            Ok(()) => println!("Reloaded TLS certificate"),
+            Err(e) => eprintln!("Can't reload TLS certificate: {}", e),
+        }
+    }
+}